@@ -4,7 +4,9 @@ extern crate rand;
 extern crate test;
 
 use kdtree::distance::squared_euclidean;
+use kdtree::ArenaKdTree;
 use kdtree::KdTree;
+use kdtree::KdTreeN;
 use test::Bencher;
 
 fn rand_data() -> ([f64; 3], f64) {
@@ -20,8 +22,8 @@ fn bench_add_to_kdtree_with_1k_3d_points(b: &mut Bencher) {
     for _ in 0..len {
         points.push(rand_data());
     }
-    for i in 0..points.len() {
-        kdtree.add(&points[i].0, points[i].1).unwrap();
+    for point in points.iter() {
+        kdtree.add(&point.0, point.1).unwrap();
     }
     b.iter(|| kdtree.add(&point.0, point.1).unwrap());
 }
@@ -35,8 +37,82 @@ fn bench_nearest_from_kdtree_with_1k_3d_points(b: &mut Bencher) {
     for _ in 0..len {
         points.push(rand_data());
     }
-    for i in 0..points.len() {
-        kdtree.add(&points[i].0, points[i].1).unwrap();
+    for point in points.iter() {
+        kdtree.add(&point.0, point.1).unwrap();
     }
     b.iter(|| kdtree.nearest(&point.0, 8, &squared_euclidean).unwrap());
 }
+
+#[bench]
+fn bench_remove_and_re_add_to_kdtree_with_1k_3d_points(b: &mut Bencher) {
+    let points: Vec<_> = (0..1000).map(|_| rand_data()).collect();
+    let mut kdtree = KdTree::build_with_capacity(3, 16, points.clone()).unwrap();
+    let mut i = 0;
+    b.iter(|| {
+        let (point, data) = points[i % points.len()];
+        kdtree.remove(&point, &data).unwrap();
+        kdtree.add(point, data).unwrap();
+        i += 1;
+    });
+}
+
+#[bench]
+fn bench_nearest_from_built_kdtree_with_1k_3d_points(b: &mut Bencher) {
+    let len = 1000usize;
+    let point = rand_data();
+    let mut points = vec![];
+    for _ in 0..len {
+        points.push(rand_data());
+    }
+    let kdtree = KdTree::build_with_capacity(3, 16, points).unwrap();
+    b.iter(|| kdtree.nearest(&point.0, 8, &squared_euclidean).unwrap());
+}
+
+#[bench]
+fn bench_build_kdtree_with_1k_3d_points(b: &mut Bencher) {
+    let points: Vec<_> = (0..1000).map(|_| rand_data()).collect();
+    b.iter(|| KdTree::build(3, points.clone()).unwrap());
+}
+
+#[bench]
+fn bench_build_fixed_kdtree_with_1k_3d_points(b: &mut Bencher) {
+    let points: Vec<_> = (0..1000).map(|_| rand_data()).collect();
+    b.iter(|| KdTreeN::<f64, f64, [f64; 3], 3>::build_fixed(points.clone()).unwrap());
+}
+
+#[bench]
+fn bench_build_arena_kdtree_with_1k_3d_points(b: &mut Bencher) {
+    let points: Vec<_> = (0..1000).map(|_| rand_data()).collect();
+    b.iter(|| ArenaKdTree::build(3, points.clone()).unwrap());
+}
+
+#[bench]
+fn bench_nearest_from_arena_kdtree_with_1k_3d_points(b: &mut Bencher) {
+    let point = rand_data();
+    let points: Vec<_> = (0..1000).map(|_| rand_data()).collect();
+    let kdtree = ArenaKdTree::build_with_capacity(3, 16, points).unwrap();
+    b.iter(|| kdtree.nearest(&point.0, 8, &squared_euclidean).unwrap());
+}
+
+fn vec_points(len: usize) -> Vec<(Vec<f64>, f64)> {
+    (0..len)
+        .map(|_| {
+            let (point, data) = rand_data();
+            (point.to_vec(), data)
+        })
+        .collect()
+}
+
+#[bench]
+fn bench_nearest_from_kdtree_with_10k_3d_vec_points_in_64_point_leaves(b: &mut Bencher) {
+    let point = ([0.5, 0.5, 0.5], 0.0);
+    let kdtree = KdTree::build_with_capacity(3, 64, vec_points(10000)).unwrap();
+    b.iter(|| kdtree.nearest(&point.0, 8, &squared_euclidean).unwrap());
+}
+
+#[bench]
+fn bench_nearest_from_arena_kdtree_with_10k_3d_vec_points_in_64_point_leaves(b: &mut Bencher) {
+    let point = ([0.5, 0.5, 0.5], 0.0);
+    let kdtree = ArenaKdTree::build_with_capacity(3, 64, vec_points(10000)).unwrap();
+    b.iter(|| kdtree.nearest(&point.0, 8, &squared_euclidean).unwrap());
+}
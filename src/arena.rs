@@ -0,0 +1,464 @@
+//! A read-only kd-tree stored in a handful of flat vectors instead of one
+//! allocation per node, for large point sets that are built once and then
+//! only queried.
+
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+
+use num_traits::{Float, One, Zero};
+
+use crate::heap_element::HeapElement;
+use crate::kdtree::ErrorKind;
+use crate::util;
+
+const DEFAULT_CAPACITY: usize = 16;
+
+/// A kd-tree whose nodes live in a single `Vec` and refer to their children
+/// by index, with the data of every leaf kept in one more shared `Vec`. The
+/// coordinates are copied out of the points into a flat buffer, `dimensions`
+/// values per point, so scanning a leaf reads them in order instead of
+/// following a pointer per point when they are stored as `Vec`s.
+///
+/// Building one allocates a few large buffers rather than a `Box` per node
+/// and two `Vec`s per leaf, and queries walk memory that sits close together.
+/// It is split the same way as `KdTree::build` and answers `nearest`,
+/// `nearest_one`, `nearest_within`, `iter_nearest`, `within`, `count_within`
+/// and `within_bbox` the same way, but cannot be changed once built. Other
+/// `KdTree` queries are not offered; `iter` lists the points to load them
+/// into a `KdTree` when one is needed.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct ArenaKdTree<A, T> {
+    dimensions: usize,
+    capacity: usize,
+    nodes: Vec<Node<A>>,
+    // the min and then max bounds of every node, `2 * dimensions` apiece
+    bounds: Vec<A>,
+    coordinates: Vec<A>,
+    bucket: Vec<T>,
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+enum Node<A> {
+    Stem {
+        split_dimension: usize,
+        split_value: A,
+        left: usize,
+        right: usize,
+    },
+    // the range of points held by the leaf, indexing `bucket`
+    Leaf {
+        start: usize,
+        end: usize,
+    },
+}
+
+impl<A: Float + Zero + One, T> ArenaKdTree<A, T> {
+    pub fn build<U: AsRef<[A]>>(dimensions: usize, items: Vec<(U, T)>) -> Result<Self, ErrorKind> {
+        ArenaKdTree::build_with_capacity(dimensions, DEFAULT_CAPACITY, items)
+    }
+
+    /// Fails with `WrongDimension` for zero dimensions, which leave nothing
+    /// to split on.
+    pub fn build_with_capacity<U: AsRef<[A]>>(
+        dimensions: usize,
+        capacity: usize,
+        mut items: Vec<(U, T)>,
+    ) -> Result<Self, ErrorKind> {
+        if dimensions == 0 {
+            return Err(ErrorKind::WrongDimension);
+        }
+        if capacity == 0 && !items.is_empty() {
+            return Err(ErrorKind::ZeroCapacity);
+        }
+        for (point, _) in items.iter() {
+            util::check_point(dimensions, point.as_ref())?;
+        }
+        let mut tree = ArenaKdTree {
+            dimensions,
+            capacity,
+            nodes: vec![],
+            bounds: vec![],
+            coordinates: Vec::with_capacity(dimensions * items.len()),
+            bucket: Vec::with_capacity(items.len()),
+        };
+        if !items.is_empty() {
+            tree.build_node(&mut items, 0);
+        }
+        for (point, data) in items {
+            tree.coordinates.extend_from_slice(point.as_ref());
+            tree.bucket.push(data);
+        }
+        Ok(tree)
+    }
+
+    /// Adds the node for `items`, which will sit at `offset` in `bucket`, and
+    /// returns its index. The items are reordered in place so that every
+    /// leaf ends up owning a contiguous range.
+    fn build_node<U: AsRef<[A]>>(&mut self, items: &mut [(U, T)], offset: usize) -> usize {
+        let index = self.nodes.len();
+        let dims = self.dimensions;
+        self.bounds.extend((0..dims).map(|_| A::infinity()));
+        self.bounds.extend((0..dims).map(|_| A::neg_infinity()));
+        let (min, max) = self.bounds[index * 2 * dims..].split_at_mut(dims);
+        for (point, _) in items.iter() {
+            for (i, &v) in point.as_ref().iter().enumerate() {
+                min[i] = min[i].min(v);
+                max[i] = max[i].max(v);
+            }
+        }
+        self.nodes.push(Node::Leaf {
+            start: offset,
+            end: offset + items.len(),
+        });
+        if items.len() <= self.capacity {
+            return index;
+        }
+        let dim = match self.widest_dimension(index) {
+            None => return index,
+            Some(dim) => dim,
+        };
+        items.sort_unstable_by(|a, b| a.0.as_ref()[dim].partial_cmp(&b.0.as_ref()[dim]).unwrap());
+        // picks the same split as `KdTree::build`
+        let mut mid = items.len() / 2;
+        let min = items[0].0.as_ref()[dim];
+        if items[mid].0.as_ref()[dim] == min {
+            mid = items.iter().position(|x| x.0.as_ref()[dim] > min).unwrap();
+        }
+        let split_value = items[mid].0.as_ref()[dim];
+        while items[mid - 1].0.as_ref()[dim] == split_value {
+            mid -= 1;
+        }
+        let (left_items, right_items) = items.split_at_mut(mid);
+        let left = self.build_node(left_items, offset);
+        let right = self.build_node(right_items, offset + mid);
+        self.nodes[index] = Node::Stem {
+            split_dimension: dim,
+            split_value,
+            left,
+            right,
+        };
+        index
+    }
+
+    fn widest_dimension(&self, node: usize) -> Option<usize> {
+        let (min, max) = self.node_bounds(node);
+        let mut widest = None;
+        let mut widest_diff = A::zero();
+        for dim in 0..self.dimensions {
+            let diff = max[dim] - min[dim];
+            if diff > widest_diff {
+                widest_diff = diff;
+                widest = Some(dim);
+            }
+        }
+        widest
+    }
+
+    fn node_bounds(&self, node: usize) -> (&[A], &[A]) {
+        let dims = self.dimensions;
+        self.bounds[node * 2 * dims..(node + 1) * 2 * dims].split_at(dims)
+    }
+
+    pub fn size(&self) -> usize {
+        self.bucket.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bucket.is_empty()
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    pub fn nearest<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        util::check_point(self.dimensions, point)?;
+        self.search(point, num, A::infinity(), distance)
+    }
+
+    pub fn nearest_one<F>(&self, point: &[A], distance: &F) -> Result<Option<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        Ok(self.nearest(point, 1, distance)?.pop())
+    }
+
+    /// Iterates over every point's coordinates and data, leaf by leaf.
+    pub fn iter(&self) -> impl Iterator<Item = (&[A], &T)> + '_ {
+        self.coordinates
+            .chunks_exact(self.dimensions)
+            .zip(self.bucket.iter())
+    }
+
+    /// Like `nearest`, but only returns points within `max_distance` of
+    /// `point`, as `KdTree::nearest_within` does.
+    pub fn nearest_within<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        max_distance: A,
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        util::check_point(self.dimensions, point)?;
+        util::check_radius(max_distance)?;
+        self.search(point, num, max_distance, distance)
+    }
+
+    /// Lazily yields points in nondecreasing distance from `point`, like
+    /// `KdTree::iter_nearest`.
+    pub fn iter_nearest<'a, 'b, F>(
+        &'b self,
+        point: &'a [A],
+        distance: &'a F,
+    ) -> Result<ArenaNearestIter<'a, 'b, A, T, F>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        util::check_point(self.dimensions, point)?;
+        let mut pending = BinaryHeap::new();
+        if !self.is_empty() {
+            pending.push(HeapElement {
+                distance: A::zero(),
+                element: 0,
+            });
+        }
+        Ok(ArenaNearestIter {
+            tree: self,
+            point,
+            distance,
+            pending,
+            evaluated: BinaryHeap::new(),
+        })
+    }
+
+    pub fn within<F>(&self, point: &[A], radius: A, distance: &F) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        util::check_point(self.dimensions, point)?;
+        util::check_radius(radius)?;
+        self.search(point, self.size(), radius, distance)
+    }
+
+    /// Counts the points within `radius` of `point` without collecting them.
+    pub fn count_within<F>(&self, point: &[A], radius: A, distance: &F) -> Result<usize, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        util::check_point(self.dimensions, point)?;
+        util::check_radius(radius)?;
+        let mut count = 0;
+        let mut pending = if self.is_empty() { vec![] } else { vec![0] };
+        while let Some(curr) = pending.pop() {
+            let (min, max) = self.node_bounds(curr);
+            if util::distance_to_space(point, min, max, distance) > radius {
+                continue;
+            }
+            match self.nodes[curr] {
+                Node::Stem { left, right, .. } => {
+                    pending.push(left);
+                    pending.push(right);
+                }
+                Node::Leaf { start, end } => {
+                    count += self
+                        .leaf_points(start, end)
+                        .filter(|&p| distance(point, p) <= radius)
+                        .count();
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns the data of every point inside the box spanned by `min` and
+    /// `max`, bounds included, in no particular order. Fails with
+    /// `InvalidBounds` if `min` exceeds `max` along any dimension.
+    pub fn within_bbox(&self, min: &[A], max: &[A]) -> Result<Vec<&T>, ErrorKind> {
+        util::check_point(self.dimensions, min)?;
+        util::check_point(self.dimensions, max)?;
+        if min.iter().zip(max.iter()).any(|(l, h)| l > h) {
+            return Err(ErrorKind::InvalidBounds);
+        }
+        let mut found = vec![];
+        let mut pending = if self.is_empty() { vec![] } else { vec![0] };
+        while let Some(curr) = pending.pop() {
+            let (lo, hi) = self.node_bounds(curr);
+            let disjoint = (0..self.dimensions).any(|i| hi[i] < min[i] || lo[i] > max[i]);
+            if disjoint {
+                continue;
+            }
+            match self.nodes[curr] {
+                Node::Stem { left, right, .. } => {
+                    pending.push(left);
+                    pending.push(right);
+                }
+                Node::Leaf { start, end } => {
+                    let points = self.leaf_points(start, end);
+                    found.extend(
+                        points
+                            .zip(self.bucket[start..end].iter())
+                            .filter(|&(p, _)| util::contained_in(p, min, max))
+                            .map(|(_, d)| d),
+                    );
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    fn leaf_points(&self, start: usize, end: usize) -> core::slice::ChunksExact<'_, A> {
+        let dims = self.dimensions;
+        self.coordinates[start * dims..end * dims].chunks_exact(dims)
+    }
+
+    fn search<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        max_dist: A,
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        if num == 0 || self.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut pending = BinaryHeap::new();
+        let mut evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
+        pending.push(HeapElement {
+            distance: A::zero(),
+            element: 0,
+        });
+        while let Some(next) = pending.pop() {
+            let to_space = -next.distance;
+            let best = if evaluated.len() < num {
+                max_dist
+            } else {
+                evaluated.peek().unwrap().distance.min(max_dist)
+            };
+            if to_space > best {
+                break;
+            }
+            let mut curr = next.element;
+            while let Node::Stem {
+                split_dimension,
+                split_value,
+                left,
+                right,
+            } = self.nodes[curr]
+            {
+                let (near, far) = if point[split_dimension] < split_value {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                let (min, max) = self.node_bounds(far);
+                let far_to_space = util::distance_to_space(point, min, max, distance);
+                if far_to_space <= best {
+                    pending.push(HeapElement {
+                        distance: -far_to_space,
+                        element: far,
+                    });
+                }
+                curr = near;
+            }
+            if let Node::Leaf { start, end } = self.nodes[curr] {
+                let points = self.leaf_points(start, end);
+                for (p, d) in points.zip(self.bucket[start..end].iter()) {
+                    let element = HeapElement {
+                        distance: distance(point, p),
+                        element: d,
+                    };
+                    if element <= max_dist {
+                        if evaluated.len() < num {
+                            evaluated.push(element);
+                        } else if element < *evaluated.peek().unwrap() {
+                            evaluated.pop();
+                            evaluated.push(element);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(evaluated
+            .into_sorted_vec()
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+}
+
+/// The iterator returned by `ArenaKdTree::iter_nearest`.
+pub struct ArenaNearestIter<'a, 'b, A: 'a + 'b, T: 'b, F: 'a> {
+    tree: &'b ArenaKdTree<A, T>,
+    point: &'a [A],
+    distance: &'a F,
+    pending: BinaryHeap<HeapElement<A, usize>>,
+    evaluated: BinaryHeap<HeapElement<A, &'b T>>,
+}
+
+impl<'a, 'b, A: Float + Zero + One, T: 'b, F: 'a> Iterator for ArenaNearestIter<'a, 'b, A, T, F>
+where
+    F: Fn(&[A], &[A]) -> A,
+{
+    type Item = (A, &'b T);
+
+    fn next(&mut self) -> Option<(A, &'b T)> {
+        let tree = self.tree;
+        let point = self.point;
+        let distance = self.distance;
+        // both heaps hold negated distances, so they pop the nearest first
+        while !self.pending.is_empty()
+            && (self.evaluated.peek().map_or(A::infinity(), |x| -x.distance)
+                >= -self.pending.peek().unwrap().distance)
+        {
+            let mut curr = self.pending.pop().unwrap().element;
+            while let Node::Stem {
+                split_dimension,
+                split_value,
+                left,
+                right,
+            } = tree.nodes[curr]
+            {
+                let (near, far) = if point[split_dimension] < split_value {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                let (min, max) = tree.node_bounds(far);
+                self.pending.push(HeapElement {
+                    distance: -util::distance_to_space(point, min, max, distance),
+                    element: far,
+                });
+                curr = near;
+            }
+            if let Node::Leaf { start, end } = tree.nodes[curr] {
+                let points = tree.leaf_points(start, end);
+                self.evaluated
+                    .extend(
+                        points
+                            .zip(tree.bucket[start..end].iter())
+                            .map(|(p, d)| HeapElement {
+                                distance: -distance(point, p),
+                                element: d,
+                            }),
+                    );
+            }
+        }
+        self.evaluated.pop().map(|x| (-x.distance, x.element))
+    }
+}
@@ -4,6 +4,8 @@
 
 use num_traits::Float;
 
+pub use crate::util::distance_to_space;
+
 /// Returns the squared euclidean distance between two points. When you only
 /// need to compare distances, rather than having the exact distance between
 /// the points, this metric is benefitial because it avoids the expensive square
@@ -34,5 +36,330 @@ pub fn squared_euclidean<T: Float>(a: &[T], b: &[T]) -> T {
     a.iter()
         .zip(b.iter())
         .map(|(x, y)| ((*x) - (*y)) * ((*x) - (*y)))
-        .fold(T::zero(), ::std::ops::Add::add)
+        .fold(T::zero(), ::core::ops::Add::add)
+}
+
+/// Returns the manhattan (taxicab) distance between two points, i.e. the sum
+/// of the absolute differences in each dimension.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdtree::distance::manhattan;
+///
+/// assert!(0.0 == manhattan(&[0.0, 0.0], &[0.0, 0.0]));
+/// assert!(2.0 == manhattan(&[0.0, 0.0], &[1.0, 1.0]));
+/// assert!(4.0 == manhattan(&[0.0, 0.0], &[-1.0, 3.0]));
+/// ```
+///
+/// # Panics
+///
+/// Only in debug mode, the length of the slices at input will be compared.
+/// If they do not match, there will be a panic:
+///
+/// ```rust,should_panic
+/// # use kdtree::distance::manhattan;
+/// // this is broken
+/// let _ = manhattan(&[0.0, 0.0], &[1.0, 0.0, 0.0]);
+/// ```
+pub fn manhattan<T: Float>(a: &[T], b: &[T]) -> T {
+    debug_assert_eq!(a.len(), b.len());
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| ((*x) - (*y)).abs())
+        .fold(T::zero(), ::core::ops::Add::add)
+}
+
+/// Returns the chebyshev distance between two points, i.e. the largest
+/// absolute difference along any single dimension.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdtree::distance::chebyshev;
+///
+/// assert!(0.0 == chebyshev(&[0.0, 0.0], &[0.0, 0.0]));
+/// assert!(1.0 == chebyshev(&[0.0, 0.0], &[1.0, 1.0]));
+/// assert!(3.0 == chebyshev(&[0.0, 0.0], &[-1.0, 3.0]));
+/// ```
+///
+/// # Panics
+///
+/// Only in debug mode, the length of the slices at input will be compared.
+/// If they do not match, there will be a panic:
+///
+/// ```rust,should_panic
+/// # use kdtree::distance::chebyshev;
+/// // this is broken
+/// let _ = chebyshev(&[0.0, 0.0], &[1.0, 0.0, 0.0]);
+/// ```
+pub fn chebyshev<T: Float>(a: &[T], b: &[T]) -> T {
+    debug_assert_eq!(a.len(), b.len());
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| ((*x) - (*y)).abs())
+        .fold(T::zero(), T::max)
+}
+
+/// Returns a squared euclidean distance that scales each dimension's squared
+/// difference by the matching entry of `weights`, which should all be
+/// non-negative. The tree prunes with the same function it is given, so keep
+/// using the same weights for every query against a tree; mixing weightings
+/// does not corrupt the tree, but results are only meaningful per weighting.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdtree::distance::weighted_squared_euclidean;
+///
+/// let distance = weighted_squared_euclidean(&[1.0, 4.0]);
+/// assert!(0.0 == distance(&[0.0, 0.0], &[0.0, 0.0]));
+/// assert!(5.0 == distance(&[0.0, 0.0], &[1.0, 1.0]));
+/// assert!(16.0 == distance(&[0.0, 0.0], &[0.0, 2.0]));
+/// ```
+///
+/// # Panics
+///
+/// Only in debug mode, the length of the slices at input will be compared
+/// with each other and with `weights`. If they do not match, there will be a
+/// panic:
+///
+/// ```rust,should_panic
+/// # use kdtree::distance::weighted_squared_euclidean;
+/// // this is broken
+/// let _ = weighted_squared_euclidean(&[1.0, 1.0])(&[0.0, 0.0, 0.0], &[1.0, 0.0, 0.0]);
+/// ```
+pub fn weighted_squared_euclidean<T: Float>(weights: &[T]) -> impl Fn(&[T], &[T]) -> T + '_ {
+    move |a: &[T], b: &[T]| {
+        debug_assert_eq!(a.len(), b.len());
+        debug_assert_eq!(a.len(), weights.len());
+        a.iter()
+            .zip(b.iter())
+            .zip(weights.iter())
+            .map(|((x, y), w)| (*w) * ((*x) - (*y)) * ((*x) - (*y)))
+            .fold(T::zero(), ::core::ops::Add::add)
+    }
+}
+
+/// Returns the Minkowski distance of order `p`, the `p`-th root of the sum of
+/// the absolute differences in each dimension raised to the `p`-th power. An
+/// order of one gives the manhattan distance and two the euclidean one; it
+/// should be positive and finite, and is only a true metric from one up.
+///
+/// The tree prunes with the same function it is given, measuring it to the
+/// nearest point of each node's bounds, which is correct for any order, so
+/// the result can be passed to `KdTree::nearest` as is. To compare distances
+/// only, `manhattan` or `squared_euclidean` avoid the powers for those
+/// orders.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdtree::distance::minkowski;
+///
+/// let distance = minkowski(3.0);
+/// assert!(0.0 == distance(&[0.0, 0.0], &[0.0, 0.0]));
+/// assert!(2.0 == distance(&[0.0, 0.0], &[0.0, -2.0]));
+/// assert!(4.0 == minkowski(1.0)(&[0.0, 0.0], &[-1.0, 3.0]));
+/// assert!(5.0 == minkowski(2.0)(&[0.0, 0.0], &[3.0, 4.0]));
+/// ```
+///
+/// # Panics
+///
+/// Only in debug mode, the length of the slices at input will be compared.
+/// If they do not match, there will be a panic:
+///
+/// ```rust,should_panic
+/// # use kdtree::distance::minkowski;
+/// // this is broken
+/// let _ = minkowski(3.0)(&[0.0, 0.0], &[1.0, 0.0, 0.0]);
+/// ```
+pub fn minkowski<T: Float>(p: T) -> impl Fn(&[T], &[T]) -> T {
+    move |a: &[T], b: &[T]| {
+        debug_assert_eq!(a.len(), b.len());
+        a.iter()
+            .zip(b.iter())
+            .map(|(x, y)| ((*x) - (*y)).abs().powf(p))
+            .fold(T::zero(), ::core::ops::Add::add)
+            .powf(p.recip())
+    }
+}
+
+/// Returns the great-circle distance in meters between two `[latitude,
+/// longitude]` points given in degrees, using a mean earth radius of
+/// 6,371,008.8 meters.
+///
+/// The bounding-box pruning used by `KdTree::nearest` assumes a flat space and
+/// may skip closer points with this metric; use `KdTree::nearest_haversine`,
+/// which prunes with a matching spherical bound, instead.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdtree::distance::haversine;
+///
+/// let london = [51.5074f64, -0.1278];
+/// let paris = [48.8566, 2.3522];
+/// assert!(0.0 == haversine(&london, &london));
+/// assert!((haversine(&london, &paris) - 343_557.0).abs() < 1000.0);
+/// ```
+///
+/// # Panics
+///
+/// Only in debug mode, both points are checked to have exactly two
+/// coordinates:
+///
+/// ```rust,should_panic
+/// # use kdtree::distance::haversine;
+/// // this is broken
+/// let _ = haversine(&[0.0, 0.0], &[1.0, 0.0, 0.0]);
+/// ```
+pub fn haversine<T: Float>(a: &[T], b: &[T]) -> T {
+    debug_assert_eq!(a.len(), 2);
+    debug_assert_eq!(b.len(), 2);
+    let two = T::one() + T::one();
+    let lat1 = a[0].to_radians();
+    let lat2 = b[0].to_radians();
+    let dlat = (b[0] - a[0]).to_radians();
+    let dlon = (b[1] - a[1]).to_radians();
+    let h = (dlat / two).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / two).sin().powi(2);
+    two * T::from(6_371_008.8).unwrap() * h.sqrt().min(T::one()).asin()
+}
+
+/// Returns one minus the cosine similarity of two vectors, from `0` for
+/// vectors pointing the same way to `2` for opposite ones. A zero vector is
+/// treated as orthogonal to everything.
+///
+/// The bounding-box pruning used by `KdTree::nearest` assumes distances grow
+/// away from the query in every direction, which angles do not; use
+/// `KdTree::nearest_cosine`, which prunes with a matching angular bound,
+/// instead.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdtree::distance::cosine_distance;
+///
+/// assert!(0.0 == cosine_distance(&[1.0, 1.0], &[3.0, 3.0]));
+/// assert!(1.0 == cosine_distance(&[1.0, 0.0], &[0.0, 2.0]));
+/// assert!(2.0 == cosine_distance(&[1.0, 0.0], &[-1.0, 0.0]));
+/// ```
+///
+/// # Panics
+///
+/// Only in debug mode, the length of the slices at input will be compared.
+/// If they do not match, there will be a panic:
+///
+/// ```rust,should_panic
+/// # use kdtree::distance::cosine_distance;
+/// // this is broken
+/// let _ = cosine_distance(&[0.0, 0.0], &[1.0, 0.0, 0.0]);
+/// ```
+pub fn cosine_distance<T: Float>(a: &[T], b: &[T]) -> T {
+    debug_assert_eq!(a.len(), b.len());
+    let (mut dot, mut norm_a, mut norm_b) = (T::zero(), T::zero(), T::zero());
+    for (&x, &y) in a.iter().zip(b.iter()) {
+        dot = dot + x * y;
+        norm_a = norm_a + x * x;
+        norm_b = norm_b + y * y;
+    }
+    if norm_a == T::zero() || norm_b == T::zero() {
+        return T::one();
+    }
+    // rounding can push the similarity just past one
+    let similarity = (dot / (norm_a.sqrt() * norm_b.sqrt()))
+        .max(-T::one())
+        .min(T::one());
+    T::one() - similarity
+}
+
+/// A distance metric that also knows how far a point is from an axis-aligned
+/// box, which the tree uses to skip subtrees. Implementing
+/// `distance_to_bounds` directly avoids the temporary point the default
+/// implementation builds by clamping to the box.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdtree::distance::{Metric, Manhattan};
+///
+/// assert!(2.0 == Manhattan.distance(&[0.0, 0.0], &[1.0, 1.0]));
+/// assert!(1.0 == Manhattan.distance_to_bounds(&[0.0, 0.0], &[1.0, -1.0], &[2.0, 1.0]));
+/// ```
+pub trait Metric<T: Float> {
+    fn distance(&self, a: &[T], b: &[T]) -> T;
+
+    /// The distance from `point` to the nearest point of the box spanned by
+    /// `min` and `max`, which must never exceed the distance to any point
+    /// inside it.
+    fn distance_to_bounds(&self, point: &[T], min: &[T], max: &[T]) -> T {
+        crate::util::distance_to_space(point, min, max, &|a: &[T], b: &[T]| self.distance(a, b))
+    }
+}
+
+/// The euclidean distance, see `squared_euclidean`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Euclidean;
+
+/// The squared euclidean distance, see `squared_euclidean`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SquaredEuclidean;
+
+/// The manhattan distance, see `manhattan`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Manhattan;
+
+/// The chebyshev distance, see `chebyshev`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Chebyshev;
+
+fn gaps<'a, T: Float>(point: &'a [T], min: &'a [T], max: &'a [T]) -> impl Iterator<Item = T> + 'a {
+    debug_assert_eq!(point.len(), min.len());
+    debug_assert_eq!(point.len(), max.len());
+    point
+        .iter()
+        .zip(min.iter().zip(max.iter()))
+        .map(|(p, (l, h))| (*l - *p).max(*p - *h).max(T::zero()))
+}
+
+impl<T: Float> Metric<T> for Euclidean {
+    fn distance(&self, a: &[T], b: &[T]) -> T {
+        squared_euclidean(a, b).sqrt()
+    }
+
+    fn distance_to_bounds(&self, point: &[T], min: &[T], max: &[T]) -> T {
+        SquaredEuclidean.distance_to_bounds(point, min, max).sqrt()
+    }
+}
+
+impl<T: Float> Metric<T> for SquaredEuclidean {
+    fn distance(&self, a: &[T], b: &[T]) -> T {
+        squared_euclidean(a, b)
+    }
+
+    fn distance_to_bounds(&self, point: &[T], min: &[T], max: &[T]) -> T {
+        gaps(point, min, max)
+            .map(|d| d * d)
+            .fold(T::zero(), ::core::ops::Add::add)
+    }
+}
+
+impl<T: Float> Metric<T> for Manhattan {
+    fn distance(&self, a: &[T], b: &[T]) -> T {
+        manhattan(a, b)
+    }
+
+    fn distance_to_bounds(&self, point: &[T], min: &[T], max: &[T]) -> T {
+        gaps(point, min, max).fold(T::zero(), ::core::ops::Add::add)
+    }
+}
+
+impl<T: Float> Metric<T> for Chebyshev {
+    fn distance(&self, a: &[T], b: &[T]) -> T {
+        chebyshev(a, b)
+    }
+
+    fn distance_to_bounds(&self, point: &[T], min: &[T], max: &[T]) -> T {
+        gaps(point, min, max).fold(T::zero(), T::max)
+    }
 }
@@ -0,0 +1,318 @@
+//! A kd-tree over integer coordinates, for pixels, grid cells and other
+//! points that live on a lattice.
+
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::heap_element::HeapElement;
+use crate::kdtree::ErrorKind;
+
+const DEFAULT_CAPACITY: usize = 16;
+
+/// A `K`-dimensional tree whose points are `[i64; K]` and whose distances are
+/// exact squared euclidean distances as `u128`.
+///
+/// Bounds, splits and distances all stay in integers, so nothing is rounded
+/// and there are no non-finite coordinates to check for. Each coordinate
+/// difference is taken in `i128`, and its square always fits in a `u128`;
+/// only the sum over two or more dimensions of differences close to the full
+/// `i64` range can overflow, and it saturates at `u128::MAX` if it does, so
+/// such points compare as equally far.
+#[derive(Clone, Debug)]
+pub struct GridKdTree<T, const K: usize> {
+    capacity: usize,
+    size: usize,
+    min_bounds: [i64; K],
+    max_bounds: [i64; K],
+    node: Node<T, K>,
+}
+
+#[derive(Clone, Debug)]
+enum Node<T, const K: usize> {
+    // points below `split_value` along `split_dimension` go left
+    Stem {
+        split_dimension: usize,
+        split_value: i64,
+        left: Box<GridKdTree<T, K>>,
+        right: Box<GridKdTree<T, K>>,
+    },
+    Leaf {
+        points: Vec<[i64; K]>,
+        bucket: Vec<T>,
+    },
+}
+
+impl<T, const K: usize> GridKdTree<T, K> {
+    pub fn new() -> Self {
+        GridKdTree::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        GridKdTree {
+            capacity,
+            size: 0,
+            min_bounds: [i64::MAX; K],
+            max_bounds: [i64::MIN; K],
+            node: Node::Leaf {
+                points: vec![],
+                bucket: vec![],
+            },
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Fails only with `ZeroCapacity`, since integer points always have `K`
+    /// valid coordinates.
+    pub fn add(&mut self, point: [i64; K], data: T) -> Result<(), ErrorKind> {
+        if self.capacity == 0 {
+            return Err(ErrorKind::ZeroCapacity);
+        }
+        self.add_unchecked(point, data);
+        Ok(())
+    }
+
+    fn add_unchecked(&mut self, point: [i64; K], data: T) {
+        self.size += 1;
+        self.extend_bounds(&point);
+        match self.node {
+            Node::Stem {
+                split_dimension,
+                split_value,
+                ref mut left,
+                ref mut right,
+            } => {
+                if point[split_dimension] < split_value {
+                    left.add_unchecked(point, data)
+                } else {
+                    right.add_unchecked(point, data)
+                }
+            }
+            Node::Leaf {
+                ref mut points,
+                ref mut bucket,
+            } => {
+                points.push(point);
+                bucket.push(data);
+                if self.size > self.capacity {
+                    self.split();
+                }
+            }
+        }
+    }
+
+    /// Splits a full leaf halfway along its widest dimension. A leaf whose
+    /// points are all identical stays a leaf until a different one arrives.
+    fn split(&mut self) {
+        let dim = match self.widest_dimension() {
+            Some(dim) => dim,
+            None => return,
+        };
+        let (low, high) = (self.min_bounds[dim], self.max_bounds[dim]);
+        // above `low` and at most `high`, so both halves get a point; the sum
+        // is taken in `i128` so that it cannot overflow
+        let split_value = ((i128::from(low) + i128::from(high)).div_euclid(2) + 1) as i64;
+        let empty = Node::Leaf {
+            points: vec![],
+            bucket: vec![],
+        };
+        let mut left = Box::new(GridKdTree::with_capacity(self.capacity));
+        let mut right = Box::new(GridKdTree::with_capacity(self.capacity));
+        if let Node::Leaf { points, bucket } = core::mem::replace(&mut self.node, empty) {
+            for (point, data) in points.into_iter().zip(bucket) {
+                if point[dim] < split_value {
+                    left.add_unchecked(point, data);
+                } else {
+                    right.add_unchecked(point, data);
+                }
+            }
+        }
+        self.node = Node::Stem {
+            split_dimension: dim,
+            split_value,
+            left,
+            right,
+        };
+    }
+
+    /// Returns the dimension in which the bounds are widest, or `None` if
+    /// they are a single point.
+    fn widest_dimension(&self) -> Option<usize> {
+        let mut max = 0;
+        let mut widest = None;
+        for (dim, (&low, &high)) in self
+            .min_bounds
+            .iter()
+            .zip(self.max_bounds.iter())
+            .enumerate()
+        {
+            let diff = i128::from(high) - i128::from(low);
+            if diff > max {
+                max = diff;
+                widest = Some(dim);
+            }
+        }
+        widest
+    }
+
+    fn extend_bounds(&mut self, point: &[i64; K]) {
+        let min = self.min_bounds.iter_mut();
+        let max = self.max_bounds.iter_mut();
+        for ((l, h), &v) in min.zip(max).zip(point.iter()) {
+            if v < *l {
+                *l = v
+            }
+            if v > *h {
+                *h = v
+            }
+        }
+    }
+
+    /// The squared distance from `point` to the nearest point within the
+    /// bounds, zero if it lies inside them.
+    fn distance_to_bounds(&self, point: &[i64; K]) -> u128 {
+        let bounds = self.min_bounds.iter().zip(self.max_bounds.iter());
+        point
+            .iter()
+            .zip(bounds)
+            .fold(0, |sum: u128, (&v, (&low, &high))| {
+                let gap = if v < low {
+                    squared_difference(low, v)
+                } else if v > high {
+                    squared_difference(v, high)
+                } else {
+                    0
+                };
+                sum.saturating_add(gap)
+            })
+    }
+
+    /// Returns up to `num` points closest to `point`, nearest first, with
+    /// their squared distances.
+    pub fn nearest(&self, point: &[i64; K], num: usize) -> Vec<(u128, &T)> {
+        if num == 0 || self.is_empty() {
+            return vec![];
+        }
+        // the heap of nodes to visit is reversed to pop the nearest one first
+        let mut pending = BinaryHeap::new();
+        let mut evaluated = BinaryHeap::<HeapElement<u128, &T>>::new();
+        pending.push(Reverse(HeapElement {
+            distance: self.distance_to_bounds(point),
+            element: self,
+        }));
+        while let Some(Reverse(next)) = pending.pop() {
+            if evaluated.len() == num && next.distance > evaluated.peek().unwrap().distance {
+                break;
+            }
+            let mut curr = next.element;
+            loop {
+                match curr.node {
+                    Node::Stem {
+                        split_dimension,
+                        split_value,
+                        ref left,
+                        ref right,
+                    } => {
+                        let (near, far) = if point[split_dimension] < split_value {
+                            (left, right)
+                        } else {
+                            (right, left)
+                        };
+                        pending.push(Reverse(HeapElement {
+                            distance: far.distance_to_bounds(point),
+                            element: &**far,
+                        }));
+                        curr = near;
+                    }
+                    Node::Leaf {
+                        ref points,
+                        ref bucket,
+                    } => {
+                        for (p, d) in points.iter().zip(bucket.iter()) {
+                            let element = HeapElement {
+                                distance: squared_distance(point, p),
+                                element: d,
+                            };
+                            if evaluated.len() < num {
+                                evaluated.push(element);
+                            } else if element < *evaluated.peek().unwrap() {
+                                evaluated.pop();
+                                evaluated.push(element);
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        evaluated
+            .into_sorted_vec()
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+
+    pub fn nearest_one(&self, point: &[i64; K]) -> Option<(u128, &T)> {
+        self.nearest(point, 1).pop()
+    }
+
+    /// Returns every point whose squared distance from `point` is at most
+    /// `radius_squared`, nearest first.
+    pub fn within(&self, point: &[i64; K], radius_squared: u128) -> Vec<(u128, &T)> {
+        let mut found = vec![];
+        let mut pending = vec![self];
+        while let Some(curr) = pending.pop() {
+            if curr.is_empty() || curr.distance_to_bounds(point) > radius_squared {
+                continue;
+            }
+            match curr.node {
+                Node::Stem {
+                    ref left,
+                    ref right,
+                    ..
+                } => {
+                    pending.push(&**right);
+                    pending.push(&**left);
+                }
+                Node::Leaf {
+                    ref points,
+                    ref bucket,
+                } => found.extend(
+                    points
+                        .iter()
+                        .zip(bucket.iter())
+                        .map(|(p, d)| (squared_distance(point, p), d))
+                        .filter(|&(distance, _)| distance <= radius_squared),
+                ),
+            }
+        }
+        found.sort_by_key(|&(distance, _)| distance);
+        found
+    }
+}
+
+impl<T, const K: usize> Default for GridKdTree<T, K> {
+    fn default() -> Self {
+        GridKdTree::new()
+    }
+}
+
+fn squared_difference(a: i64, b: i64) -> u128 {
+    // the difference of two `i64`s fits in 64 bits, so its square fits
+    let diff = (i128::from(a) - i128::from(b)).unsigned_abs();
+    diff * diff
+}
+
+fn squared_distance<const K: usize>(a: &[i64; K], b: &[i64; K]) -> u128 {
+    a.iter().zip(b.iter()).fold(0, |sum: u128, (&x, &y)| {
+        sum.saturating_add(squared_difference(x, y))
+    })
+}
@@ -1,24 +1,35 @@
-use num_traits::Float;
-use std::cmp::Ordering;
+//! The entry the tree keeps in its `BinaryHeap`s while searching, exposed so
+//! custom traversals can reuse the same ordering.
 
+use core::cmp::Ordering;
+
+/// An `element` keyed by `distance`, which may be any `PartialOrd` type.
+/// Comparisons only look at the distance, treating incomparable distances
+/// such as NaN as equal, so a `BinaryHeap` of these pops the
+/// farthest element first; the tree stores negated distances in the heap of
+/// nodes still to visit to pop the nearest one instead.
+#[derive(Clone, Debug)]
 pub struct HeapElement<A, T> {
     pub distance: A,
     pub element: T,
 }
 
-impl<A: Float, T> Ord for HeapElement<A, T> {
+/// Orders by `distance` alone.
+impl<A: PartialOrd, T> Ord for HeapElement<A, T> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or(Ordering::Equal)
     }
 }
 
-impl<A: Float, T> PartialOrd for HeapElement<A, T> {
+impl<A: PartialOrd, T> PartialOrd for HeapElement<A, T> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.distance.partial_cmp(&other.distance)
+        Some(self.cmp(other))
     }
 }
 
-impl<A: Float, T> PartialOrd<A> for HeapElement<A, T>
+impl<A: PartialOrd, T> PartialOrd<A> for HeapElement<A, T>
 where
     HeapElement<A, T>: PartialEq<A>,
 {
@@ -27,22 +38,23 @@ where
     }
 }
 
-impl<A: Float, T> Eq for HeapElement<A, T> {}
+impl<A: PartialOrd, T> Eq for HeapElement<A, T> {}
 
-impl<A: Float, T> PartialEq for HeapElement<A, T> {
+impl<A: PartialOrd, T> PartialEq for HeapElement<A, T> {
     fn eq(&self, other: &Self) -> bool {
         self.distance == other.distance
     }
 }
 
-impl<A: Float, T> PartialEq<A> for HeapElement<A, T> {
+impl<A: PartialOrd, T> PartialEq<A> for HeapElement<A, T> {
     fn eq(&self, other: &A) -> bool {
         self.distance == *other
     }
 }
 
-impl<A: Float, T> Into<(A, T)> for HeapElement<A, T> {
-    fn into(self) -> (A, T) {
-        (self.distance, self.element)
+/// Unpacks into a `(distance, element)` pair, as the query methods return.
+impl<A: PartialOrd, T> From<HeapElement<A, T>> for (A, T) {
+    fn from(e: HeapElement<A, T>) -> (A, T) {
+        (e.distance, e.element)
     }
 }
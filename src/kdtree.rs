@@ -1,22 +1,39 @@
-use std::collections::BinaryHeap;
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 use num_traits::{Float, One, Zero};
 
+use crate::distance::{cosine_distance, haversine, Metric};
 use crate::heap_element::HeapElement;
 use crate::util;
 
+/// Formatting a tree with `{:?}` gives a one-line summary of its shape; use
+/// `debug_tree` to dump every node and point.
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
-#[derive(Clone, Debug)]
-pub struct KdTree<A, T, U: AsRef<[A]>> {
+#[derive(Clone)]
+pub struct KdTree<A, T, U: AsRef<[A]>, B = Box<[A]>> {
     // node
-    left: Option<Box<KdTree<A, T, U>>>,
-    right: Option<Box<KdTree<A, T, U>>>,
+    left: Option<Box<KdTree<A, T, U, B>>>,
+    right: Option<Box<KdTree<A, T, U, B>>>,
     // common
     dimensions: usize,
     capacity: usize,
+    split_threshold: usize,
     size: usize,
-    min_bounds: Box<[A]>,
-    max_bounds: Box<[A]>,
+    // the next id for `add_indexed`; only the root's counter is used, and
+    // `child` starts every other node at zero
+    next_index: usize,
+    split_strategy: SplitStrategy,
+    split_axis: SplitAxis,
+    // distance from the root, for `SplitAxis::RoundRobin`
+    level: usize,
+    min_bounds: B,
+    max_bounds: B,
+    // set on a leaf once removals may have left its bounds wider than its
+    // points, so that its next split recomputes them
+    stale_bounds: bool,
     // stem
     split_value: Option<A>,
     split_dimension: Option<usize>,
@@ -25,29 +42,276 @@ pub struct KdTree<A, T, U: AsRef<[A]>> {
     bucket: Option<Vec<T>>,
 }
 
+/// A `KdTree` whose node bounds are stored inline as `[A; K]` instead of
+/// being boxed, saving an allocation per node when the dimensions are known
+/// at compile time. Unlike `KdTree`, which needs its dimensions spelled out,
+/// it implements `Default` as an empty `K`-dimensional tree with the default
+/// capacity.
+pub type KdTreeN<A, T, U, const K: usize> = KdTree<A, T, U, [A; K]>;
+
+/// Storage for the per-node bounding box of a `KdTree`.
+pub trait Bounds<A>: AsRef<[A]> + AsMut<[A]> {
+    fn filled(dimensions: usize, value: A) -> Self;
+
+    /// Bytes allocated on the heap, outside of the node itself.
+    fn heap_size(&self) -> usize;
+}
+
+impl<A: Copy> Bounds<A> for Box<[A]> {
+    fn filled(dimensions: usize, value: A) -> Self {
+        vec![value; dimensions].into_boxed_slice()
+    }
+
+    fn heap_size(&self) -> usize {
+        self.len() * core::mem::size_of::<A>()
+    }
+}
+
+impl<A: Copy, const K: usize> Bounds<A> for [A; K] {
+    fn filled(dimensions: usize, value: A) -> Self {
+        assert_eq!(dimensions, K);
+        [value; K]
+    }
+
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ErrorKind {
     WrongDimension,
     NonFiniteCoordinate,
     ZeroCapacity,
+    InvalidBounds,
+    InvalidRadius,
+    NoPoints,
+}
+
+/// How a full leaf picks the value to split on along the dimension chosen by
+/// its `SplitAxis`.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SplitStrategy {
+    /// Split halfway between the leaf's bounds.
+    #[default]
+    Midpoint,
+    /// Split at the median of the leaf's points, which keeps both children
+    /// populated when the points are clustered.
+    Median,
+}
+
+/// How a full leaf picks the dimension to split along.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SplitAxis {
+    /// Split along the dimension in which the leaf's bounds are widest.
+    #[default]
+    Widest,
+    /// Cycle through the dimensions by depth, splitting a node at depth `d`
+    /// along dimension `d % dimensions`, so every axis gets split even when
+    /// the points are spread far wider along one of them. A leaf whose
+    /// points all share that coordinate moves on to the next dimension.
+    RoundRobin,
+}
+
+const DEFAULT_CAPACITY: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct KdTreeBuilder {
+    dimensions: Option<usize>,
+    leaf_size: usize,
+    split_threshold: Option<usize>,
+    split_strategy: SplitStrategy,
+    split_axis: SplitAxis,
+}
+
+impl KdTreeBuilder {
+    pub fn new() -> Self {
+        KdTreeBuilder {
+            dimensions: None,
+            leaf_size: DEFAULT_CAPACITY,
+            split_threshold: None,
+            split_strategy: SplitStrategy::Midpoint,
+            split_axis: SplitAxis::Widest,
+        }
+    }
+
+    pub fn dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Sets both the leaf size and the split threshold.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.leaf_size = capacity;
+        self.split_threshold = None;
+        self
+    }
+
+    /// Sets how many points the leaves made by `rebuild` and `compact` may
+    /// hold, which is the capacity unless set separately.
+    pub fn leaf_size(mut self, leaf_size: usize) -> Self {
+        self.leaf_size = leaf_size;
+        self
+    }
+
+    /// Sets how many points a leaf may grow to through `add` before it
+    /// splits, which is the leaf size unless set separately. A threshold
+    /// above the leaf size lets leaves fill up further between splits, so
+    /// fewer, larger leaves are scanned.
+    pub fn split_threshold(mut self, split_threshold: usize) -> Self {
+        self.split_threshold = Some(split_threshold);
+        self
+    }
+
+    pub fn split_strategy(mut self, split_strategy: SplitStrategy) -> Self {
+        self.split_strategy = split_strategy;
+        self
+    }
+
+    pub fn split_axis(mut self, split_axis: SplitAxis) -> Self {
+        self.split_axis = split_axis;
+        self
+    }
+
+    /// Returns `WrongDimension` if `dimensions` was never set and
+    /// `ZeroCapacity` if the leaf size or split threshold is zero.
+    pub fn build<A: Float + Zero + One, T, U: AsRef<[A]>>(
+        &self,
+    ) -> Result<KdTree<A, T, U>, ErrorKind> {
+        let dimensions = self.dimensions.ok_or(ErrorKind::WrongDimension)?;
+        let split_threshold = self.split_threshold.unwrap_or(self.leaf_size);
+        if self.leaf_size == 0 || split_threshold == 0 {
+            return Err(ErrorKind::ZeroCapacity);
+        }
+        let mut tree = KdTree::with_capacity(dimensions, self.leaf_size);
+        tree.split_threshold = split_threshold;
+        tree.split_strategy = self.split_strategy;
+        tree.split_axis = self.split_axis;
+        Ok(tree)
+    }
+}
+
+impl Default for KdTreeBuilder {
+    fn default() -> Self {
+        KdTreeBuilder::new()
+    }
 }
 
 impl<A: Float + Zero + One, T, U: AsRef<[A]>> KdTree<A, T, U> {
     pub fn new(dims: usize) -> Self {
-        KdTree::with_capacity(dims, 2_usize.pow(4))
+        KdTree::with_capacity(dims, DEFAULT_CAPACITY)
     }
 
     pub fn with_capacity(dimensions: usize, capacity: usize) -> Self {
-        let min_bounds = vec![A::infinity(); dimensions];
-        let max_bounds = vec![A::neg_infinity(); dimensions];
+        KdTree::empty(dimensions, capacity)
+    }
+
+    /// Like `with_capacity`, but allocates room up front for the points the
+    /// root will hold before it first splits, up to `expected_points` of
+    /// them. This is only a hint: the tree grows and splits as usual, and the
+    /// leaves made by splits are still allocated as they fill.
+    pub fn with_capacity_hint(dimensions: usize, capacity: usize, expected_points: usize) -> Self {
+        let mut tree = KdTree::with_capacity(dimensions, capacity);
+        // the root splits when the point past `capacity` arrives
+        tree.reserve(core::cmp::min(expected_points, capacity.saturating_add(1)));
+        tree
+    }
+
+    /// Like `with_capacity`, but starts the bounds at the box spanned by `min`
+    /// and `max` instead of empty, for a domain known up front. Points added
+    /// outside the box still widen it. The root's first split is made on the
+    /// box, at its midpoint along its widest dimension, rather than on the
+    /// points it holds by then; the new children's bounds cover only their
+    /// own points, which prunes better than the domain would.
+    ///
+    /// Fails with `WrongDimension` or `NonFiniteCoordinate` if `min` or `max`
+    /// is not a valid point, and with `InvalidBounds` if `min` exceeds `max`
+    /// along any dimension.
+    pub fn new_with_bounds(
+        dimensions: usize,
+        capacity: usize,
+        min: &[A],
+        max: &[A],
+    ) -> Result<Self, ErrorKind> {
+        util::check_point(dimensions, min)?;
+        util::check_point(dimensions, max)?;
+        if min.iter().zip(max.iter()).any(|(l, h)| l > h) {
+            return Err(ErrorKind::InvalidBounds);
+        }
+        let mut tree = KdTree::with_capacity(dimensions, capacity);
+        tree.min_bounds = min.into();
+        tree.max_bounds = max.into();
+        Ok(tree)
+    }
+
+    pub fn new_with_strategy(dimensions: usize, split_strategy: SplitStrategy) -> Self {
+        let mut tree = KdTree::new(dimensions);
+        tree.split_strategy = split_strategy;
+        tree
+    }
+
+    pub fn from_points<I>(dimensions: usize, iter: I) -> Result<Self, ErrorKind>
+    where
+        I: IntoIterator<Item = (U, T)>,
+    {
+        let mut tree = KdTree::new(dimensions);
+        for (point, data) in iter {
+            tree.add(point, data)?;
+        }
+        Ok(tree)
+    }
+
+    pub fn build(dimensions: usize, items: Vec<(U, T)>) -> Result<Self, ErrorKind> {
+        KdTree::build_with_capacity(dimensions, DEFAULT_CAPACITY, items)
+    }
+
+    pub fn build_with_capacity(
+        dimensions: usize,
+        capacity: usize,
+        items: Vec<(U, T)>,
+    ) -> Result<Self, ErrorKind> {
+        KdTree::bulk_load(dimensions, capacity, items)
+    }
+}
+
+impl<A: Float + Zero + One, T, U: AsRef<[A]>, const K: usize> KdTreeN<A, T, U, K> {
+    pub fn fixed() -> Self {
+        KdTree::fixed_with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn fixed_with_capacity(capacity: usize) -> Self {
+        KdTree::empty(K, capacity)
+    }
+
+    pub fn build_fixed(items: Vec<(U, T)>) -> Result<Self, ErrorKind> {
+        KdTree::bulk_load(K, DEFAULT_CAPACITY, items)
+    }
+}
+
+impl<A: Float + Zero + One, T, U: AsRef<[A]>, const K: usize> Default for KdTreeN<A, T, U, K> {
+    fn default() -> Self {
+        KdTree::fixed()
+    }
+}
+
+impl<A: Float + Zero + One, T, U: AsRef<[A]>, B: Bounds<A>> KdTree<A, T, U, B> {
+    fn empty(dimensions: usize, capacity: usize) -> Self {
         KdTree {
             left: None,
             right: None,
             dimensions,
             capacity,
+            split_threshold: capacity,
             size: 0,
-            min_bounds: min_bounds.into_boxed_slice(),
-            max_bounds: max_bounds.into_boxed_slice(),
+            next_index: 0,
+            split_strategy: SplitStrategy::Midpoint,
+            split_axis: SplitAxis::Widest,
+            level: 0,
+            min_bounds: B::filled(dimensions, A::infinity()),
+            max_bounds: B::filled(dimensions, A::neg_infinity()),
+            stale_bounds: false,
             split_value: None,
             split_dimension: None,
             points: Some(vec![]),
@@ -55,311 +319,2918 @@ impl<A: Float + Zero + One, T, U: AsRef<[A]>> KdTree<A, T, U> {
         }
     }
 
-    pub fn size(&self) -> usize {
-        self.size
+    fn child(&self) -> Box<Self> {
+        let mut child = KdTree::empty(self.dimensions, self.capacity);
+        child.split_threshold = self.split_threshold;
+        child.split_strategy = self.split_strategy;
+        child.split_axis = self.split_axis;
+        child.level = self.level + 1;
+        Box::new(child)
     }
 
-    pub fn nearest<F>(
-        &self,
-        point: &[A],
-        num: usize,
-        distance: &F,
-    ) -> Result<Vec<(A, &T)>, ErrorKind>
-    where
-        F: Fn(&[A], &[A]) -> A,
-    {
-        if let Err(err) = self.check_point(point) {
-            return Err(err);
+    fn bulk_load(
+        dimensions: usize,
+        capacity: usize,
+        items: Vec<(U, T)>,
+    ) -> Result<Self, ErrorKind> {
+        KdTree::bulk_load_with(dimensions, capacity, items, KdTree::build_unchecked)
+    }
+
+    fn bulk_load_with(
+        dimensions: usize,
+        capacity: usize,
+        items: Vec<(U, T)>,
+        build: fn(&mut Self, Vec<(U, T)>),
+    ) -> Result<Self, ErrorKind> {
+        let mut tree = KdTree::empty(dimensions, capacity);
+        if items.is_empty() {
+            return Ok(tree);
         }
-        let num = std::cmp::min(num, self.size);
-        if num == 0 {
-            return Ok(vec![]);
+        if capacity == 0 {
+            return Err(ErrorKind::ZeroCapacity);
         }
-        let mut pending = BinaryHeap::new();
-        let mut evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
-        pending.push(HeapElement {
-            distance: A::zero(),
-            element: self,
-        });
-        while !pending.is_empty()
-            && (evaluated.len() < num
-                || (-pending.peek().unwrap().distance <= evaluated.peek().unwrap().distance))
-        {
-            self.nearest_step(
-                point,
-                num,
-                A::infinity(),
-                distance,
-                &mut pending,
-                &mut evaluated,
-            );
+        for (point, _) in items.iter() {
+            tree.check_point(point.as_ref())?;
         }
-        Ok(evaluated
-            .into_sorted_vec()
-            .into_iter()
-            .take(num)
-            .map(Into::into)
-            .collect())
+        build(&mut tree, items);
+        Ok(tree)
     }
 
-    pub fn within<F>(&self, point: &[A], radius: A, distance: &F) -> Result<Vec<(A, &T)>, ErrorKind>
-    where
-        F: Fn(&[A], &[A]) -> A,
-    {
-        if let Err(err) = self.check_point(point) {
-            return Err(err);
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The smallest coordinate of any point added so far, per dimension, or
+    /// `INFINITY` everywhere while the tree is empty; `new_with_bounds` starts
+    /// from its box instead. Removing points does not shrink the bounds until
+    /// `recompute_bounds`, `rebuild` or a split recomputes them.
+    pub fn min_bounds(&self) -> &[A] {
+        self.min_bounds.as_ref()
+    }
+
+    /// The largest coordinate of any point added so far, per dimension, or
+    /// `NEG_INFINITY` everywhere while the tree is empty.
+    pub fn max_bounds(&self) -> &[A] {
+        self.max_bounds.as_ref()
+    }
+
+    /// The `(min_bounds, max_bounds)` box around the points, or `None` if the
+    /// tree is empty.
+    pub fn bounding_box(&self) -> Option<(&[A], &[A])> {
+        if self.is_empty() {
+            return None;
         }
-        if self.size == 0 {
-            return Ok(vec![]);
+        Some((self.min_bounds(), self.max_bounds()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn depth(&self) -> usize {
+        if self.is_leaf() {
+            return 0;
         }
-        let mut pending = BinaryHeap::new();
-        let mut evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
-        pending.push(HeapElement {
-            distance: A::zero(),
-            element: self,
-        });
-        while !pending.is_empty() && (-pending.peek().unwrap().distance <= radius) {
-            self.nearest_step(
-                point,
-                self.size,
-                radius,
-                distance,
-                &mut pending,
-                &mut evaluated,
-            );
+        let left = self.left.as_ref().unwrap().depth();
+        let right = self.right.as_ref().unwrap().depth();
+        1 + core::cmp::max(left, right)
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        if self.is_leaf() {
+            return 1;
         }
-        Ok(evaluated
-            .into_sorted_vec()
-            .into_iter()
-            .map(Into::into)
-            .collect())
+        self.left.as_ref().unwrap().leaf_count() + self.right.as_ref().unwrap().leaf_count()
     }
 
-    fn nearest_step<'b, F>(
-        &self,
-        point: &[A],
-        num: usize,
-        max_dist: A,
-        distance: &F,
-        pending: &mut BinaryHeap<HeapElement<A, &'b Self>>,
-        evaluated: &mut BinaryHeap<HeapElement<A, &'b T>>,
-    ) where
-        F: Fn(&[A], &[A]) -> A,
-    {
-        let mut curr = &*pending.pop().unwrap().element;
-        let evaluated_dist = if evaluated.len() < num {
-            A::infinity()
-        } else {
-            if max_dist < evaluated.peek().unwrap().distance {
-                max_dist
-            } else {
-                evaluated.peek().unwrap().distance
-            }
+    pub fn node_count(&self) -> usize {
+        if self.is_leaf() {
+            return 1;
+        }
+        1 + self.left.as_ref().unwrap().node_count() + self.right.as_ref().unwrap().node_count()
+    }
+
+    /// Turns every data item into a new one with `f`, keeping the points and
+    /// the shape of the tree exactly as they are. Leaves are mapped in the
+    /// order of `iter()`.
+    pub fn map<T2, G: FnMut(T) -> T2>(self, mut f: G) -> KdTree<A, T2, U, B> {
+        self.map_unchecked(&mut f)
+    }
+
+    fn map_unchecked<T2, G: FnMut(T) -> T2>(self, f: &mut G) -> KdTree<A, T2, U, B> {
+        let (left, right) = match (self.left, self.right) {
+            (Some(left), Some(right)) => (
+                Some(Box::new(left.map_unchecked(f))),
+                Some(Box::new(right.map_unchecked(f))),
+            ),
+            _ => (None, None),
         };
+        KdTree {
+            left,
+            right,
+            dimensions: self.dimensions,
+            capacity: self.capacity,
+            split_threshold: self.split_threshold,
+            size: self.size,
+            next_index: self.next_index,
+            split_strategy: self.split_strategy,
+            split_axis: self.split_axis,
+            level: self.level,
+            min_bounds: self.min_bounds,
+            max_bounds: self.max_bounds,
+            stale_bounds: self.stale_bounds,
+            split_value: self.split_value,
+            split_dimension: self.split_dimension,
+            points: self.points,
+            bucket: self
+                .bucket
+                .map(|bucket| bucket.into_iter().map(&mut *f).collect()),
+        }
+    }
 
-        while !curr.is_leaf() {
-            let candidate;
-            if curr.belongs_in_left(point) {
-                candidate = curr.right.as_ref().unwrap();
-                curr = curr.left.as_ref().unwrap();
-            } else {
-                candidate = curr.left.as_ref().unwrap();
-                curr = curr.right.as_ref().unwrap();
+    /// Checks that the tree is well formed, for use in tests: every node's
+    /// size matches the points below it, every point lies within the bounds of
+    /// each node above it and on the correct side of each split. The error
+    /// names the first node found to break one of these, by its path from the
+    /// root. Every point is checked once per level, so this is slow on large
+    /// trees.
+    pub fn validate(&self) -> Result<(), String> {
+        self.validate_node("root")
+    }
+
+    fn validate_node(&self, path: &str) -> Result<(), String> {
+        if self.min_bounds.as_ref().len() != self.dimensions
+            || self.max_bounds.as_ref().len() != self.dimensions
+        {
+            return Err(format!("{}: bounds have the wrong dimensions", path));
+        }
+        for (point, _) in self.iter() {
+            let point = point.as_ref();
+            if util::check_point(self.dimensions, point).is_err() {
+                return Err(format!("{}: holds an invalid point", path));
             }
-            let candidate_to_space = util::distance_to_space(
-                point,
-                &*candidate.min_bounds,
-                &*candidate.max_bounds,
-                distance,
-            );
-            if candidate_to_space <= evaluated_dist {
-                pending.push(HeapElement {
-                    distance: candidate_to_space * -A::one(),
-                    element: &**candidate,
-                });
+            if !util::contained_in(point, self.min_bounds.as_ref(), self.max_bounds.as_ref()) {
+                return Err(format!("{}: holds a point outside its bounds", path));
             }
         }
-
-        let points = curr.points.as_ref().unwrap().iter();
-        let bucket = curr.bucket.as_ref().unwrap().iter();
-        let iter = points.zip(bucket).map(|(p, d)| HeapElement {
-            distance: distance(point, p.as_ref()),
-            element: d,
-        });
-        for element in iter {
-            if element <= max_dist {
-                if evaluated.len() < num {
-                    evaluated.push(element);
-                } else if element < *evaluated.peek().unwrap() {
-                    evaluated.pop();
-                    evaluated.push(element);
-                }
+        if self.is_leaf() {
+            let points = self.points.as_ref().unwrap().len();
+            let bucket = self.bucket.as_ref().unwrap().len();
+            if points != bucket {
+                return Err(format!(
+                    "{}: has {} points but {} data",
+                    path, points, bucket
+                ));
+            }
+            if self.size != points {
+                return Err(format!(
+                    "{}: has size {} but holds {} points",
+                    path, self.size, points
+                ));
             }
+            return Ok(());
+        }
+        let (left, right, dim, value) = match (
+            &self.left,
+            &self.right,
+            self.split_dimension,
+            self.split_value,
+        ) {
+            (Some(left), Some(right), Some(dim), Some(value)) => (left, right, dim, value),
+            _ => return Err(format!("{}: is neither a leaf nor a stem", path)),
+        };
+        if dim >= self.dimensions {
+            return Err(format!("{}: splits on dimension {}", path, dim));
+        }
+        if self.size != left.size + right.size {
+            return Err(format!(
+                "{}: has size {} but its children hold {} points",
+                path,
+                self.size,
+                left.size + right.size
+            ));
+        }
+        if left.iter().any(|(p, _)| p.as_ref()[dim] >= value) {
+            return Err(format!(
+                "{}.left: holds a point at or above the split",
+                path
+            ));
         }
+        if right.iter().any(|(p, _)| p.as_ref()[dim] < value) {
+            return Err(format!("{}.right: holds a point below the split", path));
+        }
+        left.validate_node(&format!("{}.left", path))?;
+        right.validate_node(&format!("{}.right", path))
     }
 
-    pub fn iter_nearest<'a, 'b, F>(
-        &'b self,
-        point: &'a [A],
-        distance: &'a F,
-    ) -> Result<NearestIter<'a, 'b, A, T, U, F>, ErrorKind>
+    /// Renders the tree as a Graphviz DOT graph for debugging, e.g. with
+    /// `dot -Tpng`. Stems are labelled with the dimension and value they split
+    /// on, with edges to the `<` and `>=` sides, and leaves with how many
+    /// points they hold.
+    pub fn to_dot(&self) -> String
     where
-        F: Fn(&[A], &[A]) -> A,
+        A: core::fmt::Display,
     {
-        if let Err(err) = self.check_point(point) {
-            return Err(err);
-        }
-        let mut pending = BinaryHeap::new();
-        let evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
-        pending.push(HeapElement {
-            distance: A::zero(),
-            element: self,
-        });
-        Ok(NearestIter {
-            point,
-            pending,
-            evaluated,
-            distance,
-        })
+        let mut dot = String::from("digraph kdtree {\n");
+        self.write_dot(&mut dot, &mut 0);
+        dot.push_str("}\n");
+        dot
     }
 
-    pub fn iter_nearest_mut<'a, 'b, F>(
-        &'b mut self,
-        point: &'a [A],
-        distance: &'a F,
-    ) -> Result<NearestIterMut<'a, 'b, A, T, U, F>, ErrorKind>
+    /// Dumps every node of the tree, with its bounds and, for leaves, its
+    /// points and data, in the pretty-printed `Debug` format.
+    pub fn debug_tree(&self) -> String
     where
-        F: Fn(&[A], &[A]) -> A,
+        A: core::fmt::Debug,
+        T: core::fmt::Debug,
+        U: core::fmt::Debug,
     {
-        if let Err(err) = self.check_point(point) {
-            return Err(err);
-        }
-        let mut pending = BinaryHeap::new();
-        let evaluated = BinaryHeap::<HeapElement<A, &mut T>>::new();
-        pending.push(HeapElement {
-            distance: A::zero(),
-            element: self,
-        });
-        Ok(NearestIterMut {
-            point,
-            pending,
-            evaluated,
-            distance,
-        })
+        format!("{:#?}", TreeDump(self))
     }
 
-    pub fn add(&mut self, point: U, data: T) -> Result<(), ErrorKind> {
+    fn write_dot(&self, dot: &mut String, next_id: &mut usize) -> usize
+    where
+        A: core::fmt::Display,
+    {
+        use core::fmt::Write;
+
+        let id = *next_id;
+        *next_id += 1;
+        // writing to a `String` cannot fail
+        if self.is_leaf() {
+            let _ = writeln!(
+                dot,
+                "  n{} [shape=box, label=\"{} points\"];",
+                id, self.size
+            );
+            return id;
+        }
+        let _ = writeln!(
+            dot,
+            "  n{} [label=\"dim {} at {}\"];",
+            id,
+            self.split_dimension.unwrap(),
+            self.split_value.unwrap()
+        );
+        let left = self.left.as_ref().unwrap().write_dot(dot, next_id);
+        let right = self.right.as_ref().unwrap().write_dot(dot, next_id);
+        let _ = writeln!(dot, "  n{} -> n{} [label=\"<\"];", id, left);
+        let _ = writeln!(dot, "  n{} -> n{} [label=\">=\"];", id, right);
+        id
+    }
+
+    /// Estimates the memory used by the tree in bytes: every node, its bounds
+    /// and the allocated capacity of the leaf vectors. Heap memory owned by
+    /// the points or data themselves, e.g. a `Vec<f64>` point, is not counted.
+    pub fn memory_usage(&self) -> usize {
+        use core::mem::size_of;
+
+        let mut total =
+            size_of::<Self>() + self.min_bounds.heap_size() + self.max_bounds.heap_size();
+        if let Some(ref points) = self.points {
+            total += points.capacity() * size_of::<U>();
+        }
+        if let Some(ref bucket) = self.bucket {
+            total += bucket.capacity() * size_of::<T>();
+        }
+        if let Some(ref left) = self.left {
+            total += left.memory_usage();
+        }
+        if let Some(ref right) = self.right {
+            total += right.memory_usage();
+        }
+        total
+    }
+
+    pub fn clear(&mut self) {
+        self.left = None;
+        self.right = None;
+        self.size = 0;
+        for bound in self.min_bounds.as_mut().iter_mut() {
+            *bound = A::infinity();
+        }
+        for bound in self.max_bounds.as_mut().iter_mut() {
+            *bound = A::neg_infinity();
+        }
+        self.stale_bounds = false;
+        self.split_value = None;
+        self.split_dimension = None;
+        self.points.get_or_insert_with(Vec::new).clear();
+        self.bucket.get_or_insert_with(Vec::new).clear();
+    }
+
+    /// Reserves room for `additional` more points before the root splits.
+    /// Does nothing once the tree has split.
+    pub fn reserve(&mut self, additional: usize) {
+        if self.is_leaf() {
+            self.points.as_mut().unwrap().reserve(additional);
+            self.bucket.as_mut().unwrap().reserve(additional);
+        }
+    }
+
+    /// Rebuilds the tree from its points with median splits, as `build`
+    /// does, which undoes the imbalance left by inserting in a bad order and
+    /// tightens bounds made stale by removals.
+    pub fn rebuild(&mut self) {
+        let mut rebuilt = KdTree::empty(self.dimensions, self.capacity);
+        rebuilt.split_threshold = self.split_threshold;
+        rebuilt.split_strategy = self.split_strategy;
+        rebuilt.split_axis = self.split_axis;
+        rebuilt.next_index = self.next_index;
+        let old = core::mem::replace(self, rebuilt);
+        let mut items = Vec::with_capacity(old.size);
+        old.drain_into(&mut items);
+        if !items.is_empty() {
+            self.build_unchecked(items);
+        }
+    }
+
+    /// Shrinks the bounds of every node to the points it holds, which
+    /// removals leave untouched. Tighter bounds let queries skip more of the
+    /// tree.
+    pub fn recompute_bounds(&mut self) {
+        let dimensions = self.dimensions;
+        self.min_bounds = B::filled(dimensions, A::infinity());
+        self.max_bounds = B::filled(dimensions, A::neg_infinity());
+        if self.is_leaf() {
+            let points = self.points.take().unwrap();
+            for point in points.iter() {
+                self.extend_bounds(point.as_ref());
+            }
+            self.points = Some(points);
+            self.stale_bounds = false;
+            return;
+        }
+        let mut left = self.left.take().unwrap();
+        let mut right = self.right.take().unwrap();
+        left.recompute_bounds();
+        right.recompute_bounds();
+        // an empty child's bounds are inverted, and would widen the union
+        for child in [&left, &right].iter().filter(|child| child.size > 0) {
+            self.extend_bounds(child.min_bounds.as_ref());
+            self.extend_bounds(child.max_bounds.as_ref());
+        }
+        self.left = Some(left);
+        self.right = Some(right);
+    }
+
+    /// Like `shrink_to_fit`, but first undoes splits that removals have left
+    /// pointless: any subtree holding no more than `capacity` points becomes a
+    /// single leaf again. Bounds are kept, so they may stay looser than the
+    /// points until `recompute_bounds`.
+    pub fn compact(&mut self) {
+        if !self.is_leaf() {
+            self.left.as_mut().unwrap().compact();
+            self.right.as_mut().unwrap().compact();
+            if self.size <= self.capacity {
+                let left = self.left.take().unwrap();
+                let right = self.right.take().unwrap();
+                let mut points = left.points.unwrap();
+                let mut bucket = left.bucket.unwrap();
+                points.extend(right.points.unwrap());
+                bucket.extend(right.bucket.unwrap());
+                self.points = Some(points);
+                self.bucket = Some(bucket);
+                self.split_value = None;
+                self.split_dimension = None;
+                // the stem's bounds outlived the removals that emptied it
+                self.stale_bounds = true;
+            }
+        }
+        self.shrink_to_fit();
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        if self.is_leaf() {
+            self.points.as_mut().unwrap().shrink_to_fit();
+            self.bucket.as_mut().unwrap().shrink_to_fit();
+            return;
+        }
+        self.left.as_mut().unwrap().shrink_to_fit();
+        self.right.as_mut().unwrap().shrink_to_fit();
+    }
+
+    /// Returns up to `num` points closest to `point`, nearest first, each with
+    /// exactly the distance `distance` returned for it. Subtrees are pruned
+    /// with the same closure, so a squared metric such as `squared_euclidean`
+    /// works as is and saves the square root when only the order matters.
+    ///
+    /// Points at equal distances come in an unspecified order, and when they
+    /// tie for the last place which of them are returned is unspecified too.
+    /// Both are the same every time for trees built by the same calls in the
+    /// same order, but change with the shape of the tree; use
+    /// `nearest_ordered_by` to fix them.
+    pub fn nearest<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.nearest_approx(point, num, A::zero(), distance)
+    }
+
+    /// Like `nearest`, but breaks ties between points at equal distances with
+    /// `compare` on their data, smallest first, including ties for the last
+    /// place. This costs a second search for every point as close as the
+    /// `num`-th one.
+    pub fn nearest_ordered_by<F, C>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+        mut compare: C,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+        C: FnMut(&T, &T) -> core::cmp::Ordering,
+    {
+        let mut nearest = self.nearest(point, num, distance)?;
+        if num > 0 && nearest.len() == num && !nearest[num - 1].0.is_nan() {
+            // pick up every point tied with the last one; the lazy search has
+            // no radius to reject a negative cutoff from a custom metric
+            let cutoff = nearest[num - 1].0;
+            nearest = self
+                .iter_nearest(point, distance)?
+                .take_while(|x| x.0.partial_cmp(&cutoff) != Some(core::cmp::Ordering::Greater))
+                .collect();
+        }
+        nearest.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0)
+                .unwrap_or(core::cmp::Ordering::Equal)
+                .then_with(|| compare(a.1, b.1))
+        });
+        nearest.truncate(num);
+        Ok(nearest)
+    }
+
+    /// Like `nearest`, but for a metric returning any ordered `D` instead of
+    /// `A`, such as a tuple of a grid distance and a tie-breaker. Subtrees
+    /// are ranked by `distance` to their closest point, which is only a lower
+    /// bound when `distance` never decreases as a coordinate moves away from
+    /// `point`; most metrics, and lexicographic tuples of them, qualify.
+    pub fn nearest_by_key<D, F>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+    ) -> Result<Vec<(D, &T)>, ErrorKind>
+    where
+        D: PartialOrd + Copy,
+        F: Fn(&[A], &[A]) -> D,
+    {
+        self.check_point(point)?;
+        let nearest = self.nearest_entries(point, num, distance);
+        Ok(nearest
+            .into_iter()
+            .map(|(dist, _, data)| (dist, data))
+            .collect())
+    }
+
+    /// The search behind `nearest_by_key`, which also returns the stored
+    /// point of every result. Points are told apart by the address of their
+    /// entry in the leaf, which unlike the address of their data is never
+    /// shared, even when `T` is zero-sized.
+    fn nearest_entries<D, F>(&self, point: &[A], num: usize, distance: &F) -> Vec<(D, &U, &T)>
+    where
+        D: PartialOrd + Copy,
+        F: Fn(&[A], &[A]) -> D,
+    {
+        use core::cmp::Reverse;
+
+        if num == 0 || self.is_empty() {
+            return vec![];
+        }
+        let num = core::cmp::min(num, self.size);
+        // without negation to fall back on, the heap of nodes to visit is
+        // reversed to pop the nearest one first
+        let mut pending = BinaryHeap::new();
+        let mut evaluated = BinaryHeap::<HeapElement<D, (&U, &T)>>::new();
+        pending.push(Reverse(HeapElement {
+            distance: self.distance_to_bounds(point, distance),
+            element: self,
+        }));
+        while let Some(Reverse(next)) = pending.pop() {
+            if evaluated.len() == num && next.distance > evaluated.peek().unwrap().distance {
+                break;
+            }
+            let mut curr = next.element;
+            while !curr.is_leaf() {
+                let (near, far) = if curr.belongs_in_left(point) {
+                    (curr.left.as_ref().unwrap(), curr.right.as_ref().unwrap())
+                } else {
+                    (curr.right.as_ref().unwrap(), curr.left.as_ref().unwrap())
+                };
+                pending.push(Reverse(HeapElement {
+                    distance: far.distance_to_bounds(point, distance),
+                    element: &**far,
+                }));
+                curr = near;
+            }
+            let points = curr.points.as_ref().unwrap().iter();
+            let bucket = curr.bucket.as_ref().unwrap().iter();
+            for (p, d) in points.zip(bucket) {
+                let element = HeapElement {
+                    distance: distance(point, p.as_ref()),
+                    element: (p, d),
+                };
+                if evaluated.len() < num {
+                    evaluated.push(element);
+                } else if element < *evaluated.peek().unwrap() {
+                    evaluated.pop();
+                    evaluated.push(element);
+                }
+            }
+        }
+        evaluated
+            .into_sorted_vec()
+            .into_iter()
+            .map(|e| (e.distance, e.element.0, e.element.1))
+            .collect()
+    }
+
+    fn distance_to_bounds<D, F>(&self, point: &[A], distance: &F) -> D
+    where
+        F: Fn(&[A], &[A]) -> D,
+    {
+        util::distance_to_space(
+            point,
+            self.min_bounds.as_ref(),
+            self.max_bounds.as_ref(),
+            distance,
+        )
+    }
+
+    /// Like `nearest`, but returns each result as a `Neighbor` with named
+    /// fields instead of a `(distance, data)` tuple.
+    pub fn nearest_neighbors<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+    ) -> Result<Vec<Neighbor<'_, A, T>>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let nearest = self.nearest(point, num, distance)?;
+        Ok(nearest.into_iter().map(Neighbor::from).collect())
+    }
+
+    /// Like `nearest`, but returns clones of the data, so the results do not
+    /// borrow the tree.
+    pub fn nearest_owned<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+    ) -> Result<Vec<(A, T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+        T: Clone,
+    {
+        let nearest = self.nearest(point, num, distance)?;
+        Ok(nearest
+            .into_iter()
+            .map(|(d, data)| (d, data.clone()))
+            .collect())
+    }
+
+    /// Like `nearest`, but skips any subtree that cannot hold a point closer
+    /// than `1 / (1 + epsilon)` of the current `num`-th best distance, so each
+    /// returned distance is within a factor of `1 + epsilon` of the exact one.
+    /// Zero gives exact results. Fails with `InvalidRadius` if `epsilon` is
+    /// negative or NaN, as it would for a radius.
+    pub fn nearest_approx<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        epsilon: A,
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let to_space =
+            &|p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance);
+        self.nearest_search(
+            point,
+            num,
+            A::infinity(),
+            epsilon,
+            distance,
+            to_space,
+            &|_| true,
+        )
+    }
+
+    /// Like `nearest`, but measures distances with `metric`, pruning with its
+    /// own distance to node bounds.
+    pub fn nearest_by_metric<M: Metric<A>>(
+        &self,
+        point: &[A],
+        num: usize,
+        metric: &M,
+    ) -> Result<Vec<(A, &T)>, ErrorKind> {
+        self.nearest_search(
+            point,
+            num,
+            A::infinity(),
+            A::zero(),
+            &|a: &[A], b: &[A]| metric.distance(a, b),
+            &|p: &[A], min: &[A], max: &[A]| metric.distance_to_bounds(p, min, max),
+            &|_| true,
+        )
+    }
+
+    /// Like `nearest`, but also reports how much of the tree the query
+    /// touched.
+    #[allow(clippy::type_complexity)]
+    pub fn nearest_with_stats<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+    ) -> Result<(Vec<(A, &T)>, QueryStats), ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        let mut stats = QueryStats::default();
+        if num == 0 || self.is_empty() {
+            return Ok((vec![], stats));
+        }
+        let num = core::cmp::min(num, self.size);
+        let mut pending = BinaryHeap::new();
+        let mut evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
+        self.nearest_heaps(
+            point,
+            num,
+            A::infinity(),
+            A::one(),
+            distance,
+            &|p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance),
+            &|_| true,
+            &mut pending,
+            &mut evaluated,
+            &mut stats,
+        );
+        let nearest = evaluated
+            .into_sorted_vec()
+            .into_iter()
+            .take(num)
+            .map(Into::into)
+            .collect();
+        Ok((nearest, stats))
+    }
+
+    /// Like `nearest`, but only returns points within `max_distance` of
+    /// `point`, and never descends into subtrees farther away than that. The
+    /// search is pruned by the smaller of `max_distance` and the current
+    /// `num`-th best distance, so it stops as soon as either limit is hit and
+    /// may return fewer than `num` points.
+    pub fn nearest_within<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        max_distance: A,
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let to_space =
+            |p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance);
+        self.nearest_search(
+            point,
+            num,
+            max_distance,
+            A::zero(),
+            distance,
+            &to_space,
+            &|_| true,
+        )
+    }
+
+    /// Like `nearest`, but only considers points inside the box spanned by
+    /// `min` and `max`, bounds included, so fewer than `num` results may be
+    /// returned. Subtrees outside the box are never visited, and the others
+    /// are ranked by their distance to the part of them inside it. Fails like
+    /// `within_bbox` for an invalid box.
+    pub fn nearest_in_bbox<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        min: &[A],
+        max: &[A],
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(min)?;
+        self.check_point(max)?;
+        if min.iter().zip(max.iter()).any(|(l, h)| l > h) {
+            return Err(ErrorKind::InvalidBounds);
+        }
+        // points and subtrees outside the box are infinitely far, past the
+        // largest finite limit on the search
+        let in_box = |a: &[A], b: &[A]| {
+            if util::contained_in(b, min, max) {
+                distance(a, b)
+            } else {
+                A::infinity()
+            }
+        };
+        // a subtree is ranked by the closest point of its bounds clipped to
+        // the box, written into one buffer so that ranking does not allocate
+        let closest = core::cell::RefCell::new(vec![A::zero(); self.dimensions]);
+        let to_space = |p: &[A], lo: &[A], hi: &[A]| {
+            let mut closest = closest.borrow_mut();
+            for i in 0..p.len() {
+                let (l, h) = (lo[i].max(min[i]), hi[i].min(max[i]));
+                if l > h {
+                    return A::infinity();
+                }
+                closest[i] = p[i].max(l).min(h);
+            }
+            distance(p, &closest)
+        };
+        self.nearest_search(
+            point,
+            num,
+            A::max_value(),
+            A::zero(),
+            &in_box,
+            &to_space,
+            &|_| true,
+        )
+    }
+
+    /// Like `nearest`, but only considers points whose data passes
+    /// `predicate`, so fewer than `num` results may be returned.
+    pub fn nearest_filtered<F, P>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+        predicate: &P,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+        P: Fn(&T) -> bool,
+    {
+        let to_space =
+            &|p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance);
+        self.nearest_search(
+            point,
+            num,
+            A::infinity(),
+            A::zero(),
+            distance,
+            to_space,
+            predicate,
+        )
+    }
+
+    /// Like `nearest`, but treats every dimension `d` with a finite, positive
+    /// `period[d]` as wrapping around, so points near opposite edges of the
+    /// period are neighbours. `distance` is called with the query point and
+    /// the periodic image of each stored point that lies closest to it.
+    pub fn nearest_periodic<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        period: &[A],
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        if period.len() != self.dimensions {
+            return Err(ErrorKind::WrongDimension);
+        }
+        let wrapped = |p: &[A], q: &[A]| distance(p, &util::periodic_image(p, q, period));
+        let to_space = |p: &[A], min: &[A], max: &[A]| {
+            util::distance_to_space_periodic(p, min, max, period, distance)
+        };
+        self.nearest_search(
+            point,
+            num,
+            A::infinity(),
+            A::zero(),
+            &wrapped,
+            &to_space,
+            &|_| true,
+        )
+    }
+
+    /// Like `nearest` with `distance::haversine`, pruning with a bound that
+    /// is valid on the sphere. The tree must hold `[latitude, longitude]`
+    /// points in degrees.
+    pub fn nearest_haversine(&self, point: &[A], num: usize) -> Result<Vec<(A, &T)>, ErrorKind> {
+        if self.dimensions != 2 {
+            return Err(ErrorKind::WrongDimension);
+        }
+        self.nearest_search(
+            point,
+            num,
+            A::infinity(),
+            A::zero(),
+            &haversine,
+            &util::haversine_to_space,
+            &|_| true,
+        )
+    }
+
+    /// Like `nearest` with `distance::cosine_distance`, pruning with a bound
+    /// on the angle between `point` and each node's bounding box.
+    pub fn nearest_cosine(&self, point: &[A], num: usize) -> Result<Vec<(A, &T)>, ErrorKind> {
+        self.nearest_search(
+            point,
+            num,
+            A::infinity(),
+            A::zero(),
+            &cosine_distance,
+            &util::cosine_to_space,
+            &|_| true,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn nearest_search<F, S, P>(
+        &self,
+        point: &[A],
+        num: usize,
+        max_dist: A,
+        epsilon: A,
+        distance: &F,
+        to_space: &S,
+        predicate: &P,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+        S: Fn(&[A], &[A], &[A]) -> A,
+        P: Fn(&T) -> bool,
+    {
+        self.check_point(point)?;
+        Self::check_radius(max_dist)?;
+        // a NaN factor would prune every subtree, a negative one too few
+        Self::check_radius(epsilon)?;
+        if num == 0 || self.is_empty() {
+            return Ok(vec![]);
+        }
+        let num = core::cmp::min(num, self.size);
+        let mut pending = BinaryHeap::new();
+        let mut evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
+        self.nearest_heaps(
+            point,
+            num,
+            max_dist,
+            A::one() + epsilon,
+            distance,
+            to_space,
+            predicate,
+            &mut pending,
+            &mut evaluated,
+            &mut (),
+        );
+        Ok(evaluated
+            .into_sorted_vec()
+            .into_iter()
+            .take(num)
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Like `nearest`, but writes the results into `out` and keeps its working
+    /// heaps in `scratch`, so repeated queries reuse their allocations.
+    pub fn nearest_into<'a, F>(
+        &'a self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+        out: &mut Vec<(A, &'a T)>,
+        scratch: &mut QueryScratch<'a, A, T, U, B>,
+    ) -> Result<(), ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        out.clear();
+        self.check_point(point)?;
+        if num == 0 || self.is_empty() {
+            return Ok(());
+        }
+        let num = core::cmp::min(num, self.size);
+        scratch.pending.clear();
+        scratch.evaluated.clear();
+        self.nearest_heaps(
+            point,
+            num,
+            A::infinity(),
+            A::one(),
+            distance,
+            &|p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance),
+            &|_| true,
+            &mut scratch.pending,
+            &mut scratch.evaluated,
+            &mut (),
+        );
+        while let Some(element) = scratch.evaluated.pop() {
+            out.push(element.into());
+        }
+        out.reverse();
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn nearest_heaps<'b, F, S, P, R>(
+        &'b self,
+        point: &[A],
+        num: usize,
+        max_dist: A,
+        factor: A,
+        distance: &F,
+        to_space: &S,
+        predicate: &P,
+        pending: &mut BinaryHeap<HeapElement<A, &'b Self>>,
+        evaluated: &mut BinaryHeap<HeapElement<A, &'b T>>,
+        stats: &mut R,
+    ) where
+        F: Fn(&[A], &[A]) -> A,
+        S: Fn(&[A], &[A], &[A]) -> A,
+        P: Fn(&T) -> bool,
+        R: Recorder,
+    {
+        pending.push(HeapElement {
+            distance: A::zero(),
+            element: self,
+        });
+        while Self::may_find_closer(num, max_dist, factor, pending, evaluated) {
+            self.nearest_step(
+                point, num, max_dist, factor, distance, to_space, predicate, pending, evaluated,
+                stats,
+            );
+        }
+    }
+
+    /// Returns whether the nearest pending node could still improve on the
+    /// `num` points evaluated so far.
+    fn may_find_closer<'b>(
+        num: usize,
+        max_dist: A,
+        factor: A,
+        pending: &BinaryHeap<HeapElement<A, &'b Self>>,
+        evaluated: &BinaryHeap<HeapElement<A, &'b T>>,
+    ) -> bool {
+        match pending.peek() {
+            None => false,
+            Some(next) => {
+                -next.distance <= max_dist
+                    && (evaluated.len() < num
+                        || -next.distance * factor <= evaluated.peek().unwrap().distance)
+            }
+        }
+    }
+
+    /// Like `nearest`, but gives up once the search has visited `max_nodes`
+    /// nodes, stems and leaves alike, returning the best points found so far
+    /// and whether the search finished, which makes them exact. The budget is
+    /// checked before each descent to a leaf, so up to the depth of the tree
+    /// more nodes may be visited. Nodes are visited nearest first, so a small
+    /// budget usually still finds close points.
+    #[allow(clippy::type_complexity)]
+    pub fn nearest_budget<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        max_nodes: usize,
+        distance: &F,
+    ) -> Result<(Vec<(A, &T)>, bool), ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        if num == 0 || self.is_empty() {
+            return Ok((vec![], true));
+        }
+        let num = core::cmp::min(num, self.size);
+        let mut stats = QueryStats::default();
+        let mut pending = BinaryHeap::new();
+        let mut evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
+        pending.push(HeapElement {
+            distance: A::zero(),
+            element: self,
+        });
+        let to_space =
+            |p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance);
+        let mut finished = true;
+        while Self::may_find_closer(num, A::infinity(), A::one(), &pending, &evaluated) {
+            if stats.nodes_visited >= max_nodes {
+                finished = false;
+                break;
+            }
+            self.nearest_step(
+                point,
+                num,
+                A::infinity(),
+                A::one(),
+                distance,
+                &to_space,
+                &|_| true,
+                &mut pending,
+                &mut evaluated,
+                &mut stats,
+            );
+        }
+        let nearest = evaluated
+            .into_sorted_vec()
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok((nearest, finished))
+    }
+
+    /// Returns the distance from `point` to its `k`-th nearest point, the
+    /// last distance `nearest(point, k, distance)` would return, or `None`
+    /// when `k` is zero or larger than the tree.
+    pub fn kth_distance<F>(
+        &self,
+        point: &[A],
+        k: usize,
+        distance: &F,
+    ) -> Result<Option<A>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        if k == 0 || k > self.size {
+            return Ok(None);
+        }
+        let mut pending = BinaryHeap::new();
+        let mut evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
+        self.nearest_heaps(
+            point,
+            k,
+            A::infinity(),
+            A::one(),
+            distance,
+            &|p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance),
+            &|_| true,
+            &mut pending,
+            &mut evaluated,
+            &mut (),
+        );
+        Ok(evaluated.peek().map(|farthest| farthest.distance))
+    }
+
+    /// Returns the most common data among the `k` nearest points, breaking
+    /// ties in favour of the label with the nearest point, or `None` when the
+    /// tree is empty or `k` is zero. Labels are only compared for equality,
+    /// so this takes `O(k^2)` comparisons on top of the search.
+    pub fn classify<F>(&self, point: &[A], k: usize, distance: &F) -> Result<Option<&T>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+        T: PartialEq,
+    {
+        let neighbours = self.nearest(point, k, distance)?;
+        let mut best = None;
+        let mut best_votes = 0;
+        for &(_, label) in neighbours.iter() {
+            let votes = neighbours
+                .iter()
+                .filter(|&&(_, other)| other == label)
+                .count();
+            // labels are seen nearest first, so ties keep the earlier one
+            if votes > best_votes {
+                best = Some(label);
+                best_votes = votes;
+            }
+        }
+        Ok(best)
+    }
+
+    /// Interpolates a value at `point` from the `k` nearest points by inverse
+    /// distance weighting: the average of `value` over them, each weighted by
+    /// `1 / d.powf(power)` where `d` is what `distance` returned, so a squared
+    /// metric with `power` 1 weights like euclidean distance with `power` 2.
+    /// A point at distance zero gives its value directly. Fails with
+    /// `NoPoints` when the tree is empty or `k` is zero, leaving nothing to
+    /// average.
+    pub fn idw_interpolate<F, G>(
+        &self,
+        point: &[A],
+        k: usize,
+        power: A,
+        distance: &F,
+        value: G,
+    ) -> Result<A, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+        G: Fn(&T) -> A,
+    {
+        let neighbours = self.nearest(point, k, distance)?;
+        match neighbours.first() {
+            Some(&(dist, data)) if dist == A::zero() => return Ok(value(data)),
+            Some(_) => {}
+            None => return Err(ErrorKind::NoPoints),
+        }
+        let (mut weighted, mut total) = (A::zero(), A::zero());
+        for (dist, data) in neighbours {
+            let weight = dist.powf(power).recip();
+            weighted = weighted + weight * value(data);
+            total = total + weight;
+        }
+        Ok(weighted / total)
+    }
+
+    pub fn nearest_one<F>(&self, point: &[A], distance: &F) -> Result<Option<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let mut pending = BinaryHeap::new();
+        let mut best: Option<(A, &T)> = None;
+        let mut best_dist = A::infinity();
+        pending.push(HeapElement {
+            distance: A::zero(),
+            element: self,
+        });
+        while let Some(HeapElement {
+            distance: to_space,
+            element: mut curr,
+        }) = pending.pop()
+        {
+            if -to_space > best_dist {
+                break;
+            }
+            while !curr.is_leaf() {
+                let candidate;
+                if curr.belongs_in_left(point) {
+                    candidate = curr.right.as_ref().unwrap();
+                    curr = curr.left.as_ref().unwrap();
+                } else {
+                    candidate = curr.left.as_ref().unwrap();
+                    curr = curr.right.as_ref().unwrap();
+                }
+                let candidate_to_space = util::distance_to_space(
+                    point,
+                    candidate.min_bounds.as_ref(),
+                    candidate.max_bounds.as_ref(),
+                    distance,
+                );
+                if candidate_to_space <= best_dist {
+                    pending.push(HeapElement {
+                        distance: -candidate_to_space,
+                        element: &**candidate,
+                    });
+                }
+            }
+            let points = curr.points.as_ref().unwrap().iter();
+            let bucket = curr.bucket.as_ref().unwrap().iter();
+            for (p, d) in points.zip(bucket) {
+                let dist = distance(point, p.as_ref());
+                if best.is_none() || dist < best_dist {
+                    best = Some((dist, d));
+                    best_dist = dist;
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    /// Like `nearest_one`, but returns the data mutably.
+    pub fn nearest_one_mut<F>(
+        &mut self,
+        point: &[A],
+        distance: &F,
+    ) -> Result<Option<(A, &mut T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let dist = match self.nearest_one(point, distance)? {
+            Some((dist, _)) => dist,
+            None => return Ok(None),
+        };
+        Ok(self
+            .find_within_mut(point, dist, distance)
+            .map(|data| (dist, data)))
+    }
+
+    fn find_within_mut<F>(&mut self, point: &[A], dist: A, distance: &F) -> Option<&mut T>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let to_space = util::distance_to_space(
+            point,
+            self.min_bounds.as_ref(),
+            self.max_bounds.as_ref(),
+            distance,
+        );
+        if to_space > dist {
+            return None;
+        }
+        if self.is_leaf() {
+            let points = self.points.as_ref().unwrap();
+            let i = points
+                .iter()
+                .position(|p| distance(point, p.as_ref()) <= dist)?;
+            return Some(&mut self.bucket.as_mut().unwrap()[i]);
+        }
+        let left_first = self.belongs_in_left(point);
+        let left = self.left.as_mut().unwrap();
+        let right = self.right.as_mut().unwrap();
+        let (near, far) = if left_first {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        match near.find_within_mut(point, dist, distance) {
+            Some(data) => Some(data),
+            None => far.find_within_mut(point, dist, distance),
+        }
+    }
+
+    pub fn within<F>(&self, point: &[A], radius: A, distance: &F) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        Self::check_radius(radius)?;
+        if self.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut pending = BinaryHeap::new();
+        let mut evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
+        pending.push(HeapElement {
+            distance: A::zero(),
+            element: self,
+        });
+        while !pending.is_empty() && (-pending.peek().unwrap().distance <= radius) {
+            self.nearest_step(
+                point,
+                self.size,
+                radius,
+                A::one(),
+                distance,
+                &|p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance),
+                &|_| true,
+                &mut pending,
+                &mut evaluated,
+                &mut (),
+            );
+        }
+        Ok(evaluated
+            .into_sorted_vec()
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Like `within`, but writes the results into `out` and keeps its working
+    /// heaps in `scratch`, as `nearest_into` does.
+    pub fn within_into<'a, F>(
+        &'a self,
+        point: &[A],
+        radius: A,
+        distance: &F,
+        out: &mut Vec<(A, &'a T)>,
+        scratch: &mut QueryScratch<'a, A, T, U, B>,
+    ) -> Result<(), ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        out.clear();
+        self.check_point(point)?;
+        Self::check_radius(radius)?;
+        if self.is_empty() {
+            return Ok(());
+        }
+        scratch.pending.clear();
+        scratch.evaluated.clear();
+        self.nearest_heaps(
+            point,
+            self.size,
+            radius,
+            A::one(),
+            distance,
+            &|p: &[A], min: &[A], max: &[A]| util::distance_to_space(p, min, max, distance),
+            &|_| true,
+            &mut scratch.pending,
+            &mut scratch.evaluated,
+            &mut (),
+        );
+        while let Some(element) = scratch.evaluated.pop() {
+            out.push(element.into());
+        }
+        out.reverse();
+        Ok(())
+    }
+
+    /// Like `within`, but returns the points in traversal order instead of
+    /// sorting them by distance, which saves the sort for large radii.
+    pub fn within_unsorted<F>(
+        &self,
+        point: &[A],
+        radius: A,
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.fold_within(point, radius, distance, vec![], |mut found, dist, data| {
+            found.push((dist, data));
+            found
+        })
+    }
+
+    /// Like `within`, but only returns the points at least `inner_radius` from
+    /// `point`. Subtrees are still pruned by `outer_radius` alone, so this
+    /// saves `within`'s allocations for the excluded points rather than
+    /// visiting fewer nodes. Fails with `InvalidRadius` if `inner_radius` is
+    /// greater than `outer_radius`.
+    pub fn within_annulus<F>(
+        &self,
+        point: &[A],
+        inner_radius: A,
+        outer_radius: A,
+        distance: &F,
+    ) -> Result<Vec<(A, &T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        Self::check_radius(inner_radius)?;
+        Self::check_radius(outer_radius)?;
+        if inner_radius > outer_radius {
+            return Err(ErrorKind::InvalidRadius);
+        }
+        let mut found = self.fold_within(
+            point,
+            outer_radius,
+            distance,
+            vec![],
+            |mut found, dist, data| {
+                if dist >= inner_radius {
+                    found.push(HeapElement {
+                        distance: dist,
+                        element: data,
+                    });
+                }
+                found
+            },
+        )?;
+        found.sort_unstable();
+        Ok(found.into_iter().map(Into::into).collect())
+    }
+
+    /// Returns whether a point with exactly these coordinates is stored. The
+    /// comparison is exact, so to find points that may differ by rounding
+    /// error use `within` with a small radius instead.
+    pub fn contains(&self, point: &[A]) -> Result<bool, ErrorKind> {
+        self.check_point(point)?;
+        let mut curr = self;
+        while !curr.is_leaf() {
+            curr = if curr.belongs_in_left(point) {
+                curr.left.as_ref().unwrap()
+            } else {
+                curr.right.as_ref().unwrap()
+            };
+        }
+        let mut points = curr.points.as_ref().unwrap().iter();
+        Ok(points.any(|p| p.as_ref() == point))
+    }
+
+    /// Returns the mean of every stored point, or `None` if the tree is
+    /// empty. Each leaf is summed on its own and the sums are added up the
+    /// tree, which keeps the rounding error lower than adding every point
+    /// to one running total.
+    pub fn centroid(&self) -> Option<Vec<A>> {
+        if self.is_empty() {
+            return None;
+        }
+        let count = A::from(self.size).unwrap();
+        Some(self.sums().into_iter().map(|sum| sum / count).collect())
+    }
+
+    fn sums(&self) -> Vec<A> {
+        if self.is_leaf() {
+            let mut sums = vec![A::zero(); self.dimensions];
+            for point in self.points.as_ref().unwrap().iter() {
+                for (sum, &value) in sums.iter_mut().zip(point.as_ref().iter()) {
+                    *sum = *sum + value;
+                }
+            }
+            return sums;
+        }
+        let mut sums = self.left.as_ref().unwrap().sums();
+        let right = self.right.as_ref().unwrap().sums();
+        for (sum, value) in sums.iter_mut().zip(right) {
+            *sum = *sum + value;
+        }
+        sums
+    }
+
+    /// Returns the coordinates stored with the first point, in `iter()`
+    /// order, whose data equals `data`, or `None` if there is none. This scans
+    /// every point, since the tree is indexed by coordinates only.
+    pub fn coordinates_of(&self, data: &T) -> Option<&[A]>
+    where
+        T: PartialEq,
+    {
+        self.iter()
+            .find(|&(_, d)| d == data)
+            .map(|(point, _)| point.as_ref())
+    }
+
+    /// Groups the data of points stored at exactly the same coordinates,
+    /// returning only the groups with more than one point. Such points always
+    /// share a leaf, which they keep from splitting once they fill it.
+    /// Groups come in leaf order and the data in each in `iter()` order.
+    pub fn find_duplicates(&self) -> Vec<(&[A], Vec<&T>)> {
+        let mut duplicates = vec![];
+        for (points, bucket) in self.leaves() {
+            let mut entries: Vec<_> = points.iter().zip(bucket.iter()).collect();
+            // stored coordinates are finite, so they are totally ordered; the
+            // sort is stable to keep the data in order within a group
+            entries.sort_by(|a, b| a.0.as_ref().partial_cmp(b.0.as_ref()).unwrap());
+            let mut rest = &entries[..];
+            while let Some(&(point, _)) = rest.first() {
+                let same = rest
+                    .iter()
+                    .take_while(|&&(p, _)| p.as_ref() == point.as_ref())
+                    .count();
+                if same > 1 {
+                    let data = rest[..same].iter().map(|&(_, d)| d).collect();
+                    duplicates.push((point.as_ref(), data));
+                }
+                rest = &rest[same..];
+            }
+        }
+        duplicates
+    }
+
+    /// Returns the data of every point stored at exactly these coordinates,
+    /// in `iter()` order. Points with equal coordinates always fall on the
+    /// same side of every split, so they share a leaf and this only scans
+    /// that one; a leaf holding nothing but one point repeated cannot be
+    /// split, and simply grows past its capacity.
+    pub fn all_at(&self, point: &[A]) -> Result<Vec<&T>, ErrorKind> {
+        self.check_point(point)?;
+        let mut curr = self;
+        while !curr.is_leaf() {
+            curr = if curr.belongs_in_left(point) {
+                curr.left.as_ref().unwrap()
+            } else {
+                curr.right.as_ref().unwrap()
+            };
+        }
+        let points = curr.points.as_ref().unwrap().iter();
+        let bucket = curr.bucket.as_ref().unwrap().iter();
+        Ok(points
+            .zip(bucket)
+            .filter(|&(p, _)| p.as_ref() == point)
+            .map(|(_, d)| d)
+            .collect())
+    }
+
+    /// Returns the entry for `point`, to get the data stored at exactly these
+    /// coordinates or insert some. Matching is exact, as for `contains`; if
+    /// several points share the coordinates the entry refers to one of them.
+    pub fn entry(&mut self, point: U) -> Result<Entry<'_, A, T, U, B>, ErrorKind> {
+        if self.capacity == 0 {
+            return Err(ErrorKind::ZeroCapacity);
+        }
+        self.check_point(point.as_ref())?;
+        Ok(Entry { tree: self, point })
+    }
+
+    fn exact_mut(&mut self, point: &[A]) -> Option<&mut T> {
+        let mut curr = self;
+        while !curr.is_leaf() {
+            curr = if curr.belongs_in_left(point) {
+                curr.left.as_mut().unwrap()
+            } else {
+                curr.right.as_mut().unwrap()
+            };
+        }
+        let mut points = curr.points.as_ref().unwrap().iter();
+        let index = points.position(|p| p.as_ref() == point)?;
+        Some(&mut curr.bucket.as_mut().unwrap()[index])
+    }
+
+    /// Counts the points within `radius` of `point`, like `within(..).len()`
+    /// but without collecting them.
+    pub fn count_within<F>(&self, point: &[A], radius: A, distance: &F) -> Result<usize, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.fold_within(point, radius, distance, 0, |count, _, _| count + 1)
+    }
+
+    /// Folds `f` over every point within `radius` of `point`, passing the
+    /// accumulator, the point's distance and its data, in no particular order.
+    pub fn fold_within<'a, F, Acc, Fold>(
+        &'a self,
+        point: &[A],
+        radius: A,
+        distance: &F,
+        init: Acc,
+        mut f: Fold,
+    ) -> Result<Acc, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+        Fold: FnMut(Acc, A, &'a T) -> Acc,
+    {
+        self.check_point(point)?;
+        Self::check_radius(radius)?;
+        let mut acc = init;
+        let mut pending = vec![self];
+        while let Some(curr) = pending.pop() {
+            let to_space = util::distance_to_space(
+                point,
+                curr.min_bounds.as_ref(),
+                curr.max_bounds.as_ref(),
+                distance,
+            );
+            if to_space > radius {
+                continue;
+            }
+            if curr.is_leaf() {
+                let points = curr.points.as_ref().unwrap().iter();
+                let bucket = curr.bucket.as_ref().unwrap().iter();
+                for (p, d) in points.zip(bucket) {
+                    let dist = distance(point, p.as_ref());
+                    if dist <= radius {
+                        acc = f(acc, dist, d);
+                    }
+                }
+            } else {
+                pending.push(curr.left.as_ref().unwrap());
+                pending.push(curr.right.as_ref().unwrap());
+            }
+        }
+        Ok(acc)
+    }
+
+    pub fn within_bbox(&self, min: &[A], max: &[A]) -> Result<Vec<&T>, ErrorKind> {
+        self.check_point(min)?;
+        self.check_point(max)?;
+        if min.iter().zip(max.iter()).any(|(l, h)| l > h) {
+            return Err(ErrorKind::InvalidBounds);
+        }
+        let mut found = vec![];
+        let mut pending = vec![self];
+        while let Some(curr) = pending.pop() {
+            if !curr.intersects_bbox(min, max) {
+                continue;
+            }
+            if curr.is_leaf() {
+                let points = curr.points.as_ref().unwrap().iter();
+                let bucket = curr.bucket.as_ref().unwrap().iter();
+                found.extend(
+                    points
+                        .zip(bucket)
+                        .filter(|&(p, _)| util::contained_in(p.as_ref(), min, max))
+                        .map(|(_, d)| d),
+                );
+            } else {
+                pending.push(curr.left.as_ref().unwrap());
+                pending.push(curr.right.as_ref().unwrap());
+            }
+        }
+        Ok(found)
+    }
+
+    /// Returns the data of every point whose `dim`-th coordinate lies in
+    /// `[lo, hi]`, whatever its other coordinates, in no particular order.
+    /// Either end may be infinite to leave that side open. Fails with
+    /// `WrongDimension` if `dim` is out of range, `NonFiniteCoordinate` if an
+    /// end is NaN and `InvalidBounds` if `lo > hi`.
+    pub fn within_slab(&self, dim: usize, lo: A, hi: A) -> Result<Vec<&T>, ErrorKind> {
+        if dim >= self.dimensions {
+            return Err(ErrorKind::WrongDimension);
+        }
+        if lo.is_nan() || hi.is_nan() {
+            return Err(ErrorKind::NonFiniteCoordinate);
+        }
+        if lo > hi {
+            return Err(ErrorKind::InvalidBounds);
+        }
+        let mut found = vec![];
+        let mut pending = vec![self];
+        while let Some(curr) = pending.pop() {
+            if curr.min_bounds.as_ref()[dim] > hi || curr.max_bounds.as_ref()[dim] < lo {
+                continue;
+            }
+            if curr.is_leaf() {
+                let points = curr.points.as_ref().unwrap().iter();
+                let bucket = curr.bucket.as_ref().unwrap().iter();
+                found.extend(
+                    points
+                        .zip(bucket)
+                        .filter(|&(p, _)| lo <= p.as_ref()[dim] && p.as_ref()[dim] <= hi)
+                        .map(|(_, d)| d),
+                );
+            } else {
+                pending.push(curr.left.as_ref().unwrap());
+                pending.push(curr.right.as_ref().unwrap());
+            }
+        }
+        Ok(found)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn nearest_step<'b, F, S, P, R>(
+        &self,
+        point: &[A],
+        num: usize,
+        max_dist: A,
+        factor: A,
+        distance: &F,
+        to_space: &S,
+        predicate: &P,
+        pending: &mut BinaryHeap<HeapElement<A, &'b Self>>,
+        evaluated: &mut BinaryHeap<HeapElement<A, &'b T>>,
+        stats: &mut R,
+    ) where
+        F: Fn(&[A], &[A]) -> A,
+        S: Fn(&[A], &[A], &[A]) -> A,
+        P: Fn(&T) -> bool,
+        R: Recorder,
+    {
+        let mut curr = pending.pop().unwrap().element;
+        let evaluated_dist =
+            if evaluated.len() < num || max_dist < evaluated.peek().unwrap().distance {
+                max_dist
+            } else {
+                evaluated.peek().unwrap().distance
+            };
+
+        while !curr.is_leaf() {
+            stats.node();
+            let candidate;
+            if curr.belongs_in_left(point) {
+                candidate = curr.right.as_ref().unwrap();
+                curr = curr.left.as_ref().unwrap();
+            } else {
+                candidate = curr.left.as_ref().unwrap();
+                curr = curr.right.as_ref().unwrap();
+            }
+            let candidate_to_space = to_space(
+                point,
+                candidate.min_bounds.as_ref(),
+                candidate.max_bounds.as_ref(),
+            );
+            if candidate_to_space * factor <= evaluated_dist {
+                pending.push(HeapElement {
+                    distance: candidate_to_space * -A::one(),
+                    element: &**candidate,
+                });
+            }
+        }
+
+        let points = curr.points.as_ref().unwrap().iter();
+        let bucket = curr.bucket.as_ref().unwrap().iter();
+        let iter = points
+            .zip(bucket)
+            .filter(|&(_, d)| predicate(d))
+            .map(|(p, d)| HeapElement {
+                distance: distance(point, p.as_ref()),
+                element: d,
+            });
+        stats.node();
+        stats.leaf();
+        for element in iter {
+            stats.point();
+            if element <= max_dist {
+                if evaluated.len() < num {
+                    evaluated.push(element);
+                } else if element < *evaluated.peek().unwrap() {
+                    evaluated.pop();
+                    evaluated.push(element);
+                }
+            }
+        }
+    }
+
+    /// Iterates over every `(point, data)` pair in the tree, in no particular
+    /// order.
+    pub fn iter(&self) -> Iter<'_, A, T, U, B> {
+        Iter {
+            leaves: self.leaves(),
+            leaf: None,
+        }
+    }
+
+    /// Iterates over the points and data of each leaf, left to right, so that
+    /// points close together come in the same batch. Leaves emptied by
+    /// removals, or the root of an empty tree, give empty slices.
+    pub fn leaves(&self) -> Leaves<'_, A, T, U, B> {
+        Leaves {
+            pending: vec![self],
+        }
+    }
+
+    /// Returns the `k` nearest neighbours of every stored point, in the order
+    /// of `iter()`. A point is never its own neighbour, but other points at
+    /// the same coordinates are; points are told apart by where they are
+    /// stored, so this holds for zero-sized data too.
+    pub fn knn_graph<F>(&self, k: usize, distance: &F) -> Result<Vec<Vec<(A, &T)>>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        Ok(self
+            .iter()
+            .map(|(point, _)| {
+                self.neighbours(point, k, distance)
+                    .map(|(dist, _, data)| (dist, data))
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// The `k` nearest points to the stored `point`, leaving out `point`
+    /// itself but not other points at the same coordinates.
+    fn neighbours<'a, F>(
+        &'a self,
+        point: &'a U,
+        k: usize,
+        distance: &F,
+    ) -> impl Iterator<Item = (A, &'a U, &'a T)> + 'a
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let nearest = self.nearest_entries(point.as_ref(), k.saturating_add(1), distance);
+        // ties at distance zero can leave the point itself out of the `k + 1`
+        nearest
+            .into_iter()
+            .filter(move |&(_, p, _)| !core::ptr::eq(p, point))
+            .take(k)
+    }
+
+    /// Returns every stored point's data with its distance to its `k`-th
+    /// nearest neighbour, in the order of `iter()`, as density-based outlier
+    /// scores such as LOF start from. Neighbours are found as in `knn_graph`,
+    /// so a point is never its own neighbour; a point with fewer than `k`
+    /// others gets `INFINITY`, and `k = 0` gives zero everywhere.
+    pub fn k_distances<F>(&self, k: usize, distance: &F) -> Result<Vec<(&T, A)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        Ok(self
+            .iter()
+            .map(|(point, data)| (data, self.k_distance(point, k, distance)))
+            .collect())
+    }
+
+    fn k_distance<F>(&self, point: &U, k: usize, distance: &F) -> A
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        if k == 0 {
+            return A::zero();
+        }
+        let mut neighbours = self.neighbours(point, k, distance);
+        neighbours
+            .nth(k - 1)
+            .map_or(A::infinity(), |(dist, _, _)| dist)
+    }
+
+    /// Returns the two closest distinct points in the tree, with the distance
+    /// between them, or `None` with fewer than two points. Each point is told
+    /// apart from the others as in `knn_graph`, so two points at the same
+    /// coordinates are a pair at distance zero. This runs a two-point nearest
+    /// search from every point, so it takes `n` queries.
+    pub fn closest_pair<F>(&self, distance: &F) -> Option<(A, &T, &T)>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let mut best: Option<(A, &T, &T)> = None;
+        for (point, data) in self.iter() {
+            if let Some((dist, _, other)) = self.neighbours(point, 1, distance).next() {
+                if best.is_none() || dist < best.unwrap().0 {
+                    best = Some((dist, data, other));
+                }
+            }
+        }
+        best
+    }
+
+    /// Lazily yields points in nondecreasing distance from `point`, doing only
+    /// as much of the search as the caller consumes; `.take(k)` gives the same
+    /// points as `nearest(point, k, distance)`.
+    pub fn iter_nearest<'a, 'b, F>(
+        &'b self,
+        point: &'a [A],
+        distance: &'a F,
+    ) -> Result<NearestIter<'a, 'b, A, T, U, F, B>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        let mut pending = BinaryHeap::new();
+        let evaluated = BinaryHeap::<HeapElement<A, &T>>::new();
+        pending.push(HeapElement {
+            distance: A::zero(),
+            element: self,
+        });
+        Ok(NearestIter {
+            point,
+            pending,
+            evaluated,
+            distance,
+        })
+    }
+
+    /// Like `iter_nearest`, but hands out results in batches for paged
+    /// queries: each `next_batch` picks up where the last one stopped.
+    pub fn nearest_cursor<'a, 'b, F>(
+        &'b self,
+        point: &'a [A],
+        distance: &'a F,
+    ) -> Result<NearestCursor<'a, 'b, A, T, U, F, B>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        Ok(NearestCursor {
+            iter: self.iter_nearest(point, distance)?,
+        })
+    }
+
+    pub fn iter_nearest_mut<'a, 'b, F>(
+        &'b mut self,
+        point: &'a [A],
+        distance: &'a F,
+    ) -> Result<NearestIterMut<'a, 'b, A, T, U, F, B>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        let mut pending = BinaryHeap::new();
+        let evaluated = BinaryHeap::<HeapElement<A, &mut T>>::new();
+        pending.push(HeapElement {
+            distance: A::zero(),
+            element: self,
+        });
+        Ok(NearestIterMut {
+            point,
+            pending,
+            evaluated,
+            distance,
+        })
+    }
+
+    pub fn add(&mut self, point: U, data: T) -> Result<(), ErrorKind> {
+        if self.capacity == 0 {
+            return Err(ErrorKind::ZeroCapacity);
+        }
+        self.check_point(point.as_ref())?;
+        self.add_unchecked(point, data)
+    }
+
+    /// Adds every item from `items`, returning how many were added. Stops at
+    /// the first invalid point and returns its error; the items added before
+    /// it stay in the tree.
+    pub fn add_many<I: IntoIterator<Item = (U, T)>>(
+        &mut self,
+        items: I,
+    ) -> Result<usize, ErrorKind> {
+        let mut items = items.into_iter().peekable();
+        if items.peek().is_some() && self.capacity == 0 {
+            return Err(ErrorKind::ZeroCapacity);
+        }
+        let mut added = 0;
+        for (point, data) in items {
+            self.check_point(point.as_ref())?;
+            self.add_unchecked(point, data)?;
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    pub fn merge(&mut self, other: KdTree<A, T, U, B>) -> Result<(), ErrorKind> {
+        if self.dimensions != other.dimensions {
+            return Err(ErrorKind::WrongDimension);
+        }
+        if other.size == 0 {
+            return Ok(());
+        }
         if self.capacity == 0 {
             return Err(ErrorKind::ZeroCapacity);
         }
-        if let Err(err) = self.check_point(point.as_ref()) {
-            return Err(err);
+        let mut items = Vec::with_capacity(other.size);
+        other.drain_into(&mut items);
+        for (point, data) in items {
+            self.add_unchecked(point, data)?;
+        }
+        Ok(())
+    }
+
+    fn add_unchecked(&mut self, point: U, data: T) -> Result<(), ErrorKind> {
+        if self.is_leaf() {
+            self.add_to_bucket(point, data);
+            return Ok(());
+        }
+        self.extend_bounds(point.as_ref());
+        self.size += 1;
+        let next = if self.belongs_in_left(point.as_ref()) {
+            self.left.as_mut()
+        } else {
+            self.right.as_mut()
+        };
+        next.unwrap().add_unchecked(point, data)
+    }
+
+    /// Removes every point stored at exactly `point` with data equal to
+    /// `data`, returning how many there were. This descends to the one leaf
+    /// that can hold them and scans it, and each match is replaced by the
+    /// leaf's last point rather than shifting the rest, so removal costs about
+    /// as much as `add`. Bounds are left as they were; see `recompute_bounds`.
+    pub fn remove(&mut self, point: &[A], data: &T) -> Result<usize, ErrorKind>
+    where
+        T: PartialEq,
+    {
+        self.check_point(point)?;
+        Ok(self.remove_unchecked(point, data))
+    }
+
+    fn remove_unchecked(&mut self, point: &[A], data: &T) -> usize
+    where
+        T: PartialEq,
+    {
+        let removed = if self.is_leaf() {
+            let points = self.points.as_mut().unwrap();
+            let bucket = self.bucket.as_mut().unwrap();
+            let mut removed = 0;
+            let mut i = 0;
+            while i < points.len() {
+                if points[i].as_ref() == point && bucket[i] == *data {
+                    points.swap_remove(i);
+                    bucket.swap_remove(i);
+                    removed += 1;
+                } else {
+                    i += 1;
+                }
+            }
+            self.stale_bounds |= removed > 0;
+            removed
+        } else {
+            let next = if self.belongs_in_left(point) {
+                self.left.as_mut()
+            } else {
+                self.right.as_mut()
+            };
+            next.unwrap().remove_unchecked(point, data)
+        };
+        self.size -= removed;
+        removed
+    }
+
+    /// Moves one point stored at `old` with data equal to `data` to `new`,
+    /// into whichever leaf `new` belongs in. Returns whether such a point was
+    /// found; if not, the tree is left unchanged. Both points are checked
+    /// before anything is removed.
+    pub fn move_point(&mut self, old: &[A], new: U, data: &T) -> Result<bool, ErrorKind>
+    where
+        T: PartialEq,
+    {
+        self.check_point(old)?;
+        self.check_point(new.as_ref())?;
+        match self.remove_one_unchecked(old, data) {
+            Some(data) => {
+                self.add_unchecked(new, data)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove_one_unchecked(&mut self, point: &[A], data: &T) -> Option<T>
+    where
+        T: PartialEq,
+    {
+        let removed = if self.is_leaf() {
+            let points = self.points.as_mut().unwrap();
+            let bucket = self.bucket.as_mut().unwrap();
+            let i =
+                (0..points.len()).find(|&i| points[i].as_ref() == point && bucket[i] == *data)?;
+            points.swap_remove(i);
+            self.stale_bounds = true;
+            bucket.swap_remove(i)
+        } else {
+            let next = if self.belongs_in_left(point) {
+                self.left.as_mut()
+            } else {
+                self.right.as_mut()
+            };
+            next.unwrap().remove_one_unchecked(point, data)?
+        };
+        self.size -= 1;
+        Some(removed)
+    }
+
+    /// Removes the point nearest to `point` and returns it with its distance
+    /// and data, or `None` if the tree is empty.
+    pub fn remove_nearest<F>(
+        &mut self,
+        point: &[A],
+        distance: &F,
+    ) -> Result<Option<(A, U, T)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let dist = match self.nearest_one(point, distance)? {
+            Some((dist, _)) => dist,
+            None => return Ok(None),
+        };
+        Ok(self
+            .remove_within_unchecked(point, dist, distance)
+            .map(|(p, d)| (dist, p, d)))
+    }
+
+    fn remove_within_unchecked<F>(&mut self, point: &[A], dist: A, distance: &F) -> Option<(U, T)>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let to_space = util::distance_to_space(
+            point,
+            self.min_bounds.as_ref(),
+            self.max_bounds.as_ref(),
+            distance,
+        );
+        if to_space > dist {
+            return None;
         }
-        self.add_unchecked(point, data)
+        let removed = if self.is_leaf() {
+            let points = self.points.as_mut().unwrap();
+            let bucket = self.bucket.as_mut().unwrap();
+            let i = points
+                .iter()
+                .position(|p| distance(point, p.as_ref()) <= dist)?;
+            self.stale_bounds = true;
+            (points.swap_remove(i), bucket.swap_remove(i))
+        } else {
+            let left_first = self.belongs_in_left(point);
+            let left = self.left.as_mut().unwrap();
+            let right = self.right.as_mut().unwrap();
+            let (near, far) = if left_first {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            match near.remove_within_unchecked(point, dist, distance) {
+                Some(removed) => removed,
+                None => far.remove_within_unchecked(point, dist, distance)?,
+            }
+        };
+        self.size -= 1;
+        Some(removed)
     }
 
-    fn add_unchecked(&mut self, point: U, data: T) -> Result<(), ErrorKind> {
-        if self.is_leaf() {
-            self.add_to_bucket(point, data);
-            return Ok(());
+    /// Removes every point within `radius` of `point` and returns how many
+    /// were removed, skipping subtrees whose bounds are farther away. Node
+    /// bounds are not shrunk.
+    pub fn remove_within<F>(
+        &mut self,
+        point: &[A],
+        radius: A,
+        distance: &F,
+    ) -> Result<usize, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        self.check_point(point)?;
+        Self::check_radius(radius)?;
+        Ok(self.remove_all_within_unchecked(point, radius, distance))
+    }
+
+    fn remove_all_within_unchecked<F>(&mut self, point: &[A], radius: A, distance: &F) -> usize
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        let to_space = util::distance_to_space(
+            point,
+            self.min_bounds.as_ref(),
+            self.max_bounds.as_ref(),
+            distance,
+        );
+        if self.size == 0 || to_space > radius {
+            return 0;
         }
-        self.extend(point.as_ref());
+        let removed = if self.is_leaf() {
+            let points = self.points.as_mut().unwrap();
+            let bucket = self.bucket.as_mut().unwrap();
+            let mut removed = 0;
+            let mut i = 0;
+            while i < points.len() {
+                if distance(point, points[i].as_ref()) <= radius {
+                    points.swap_remove(i);
+                    bucket.swap_remove(i);
+                    removed += 1;
+                } else {
+                    i += 1;
+                }
+            }
+            self.stale_bounds |= removed > 0;
+            removed
+        } else {
+            self.left
+                .as_mut()
+                .unwrap()
+                .remove_all_within_unchecked(point, radius, distance)
+                + self
+                    .right
+                    .as_mut()
+                    .unwrap()
+                    .remove_all_within_unchecked(point, radius, distance)
+        };
+        self.size -= removed;
+        removed
+    }
+
+    /// Keeps only the points whose data passes `predicate`. Node bounds are
+    /// not shrunk, so they may end up looser than the remaining points.
+    pub fn retain<P: FnMut(&T) -> bool>(&mut self, mut predicate: P) {
+        self.retain_unchecked(&mut predicate);
+    }
+
+    fn retain_unchecked<P: FnMut(&T) -> bool>(&mut self, predicate: &mut P) -> usize {
+        let removed = if self.is_leaf() {
+            let points = self.points.as_mut().unwrap();
+            let bucket = self.bucket.as_mut().unwrap();
+            let mut removed = 0;
+            let mut i = 0;
+            while i < bucket.len() {
+                if predicate(&bucket[i]) {
+                    i += 1;
+                } else {
+                    points.swap_remove(i);
+                    bucket.swap_remove(i);
+                    removed += 1;
+                }
+            }
+            self.stale_bounds |= removed > 0;
+            removed
+        } else {
+            self.left.as_mut().unwrap().retain_unchecked(predicate)
+                + self.right.as_mut().unwrap().retain_unchecked(predicate)
+        };
+        self.size -= removed;
+        removed
+    }
+
+    /// Removes every point for which `predicate` returns `true` and returns
+    /// them with their data, in no particular order. Bounds are left as
+    /// `retain` leaves them.
+    pub fn drain_filter<P: FnMut(&U, &T) -> bool>(&mut self, mut predicate: P) -> Vec<(U, T)> {
+        let mut drained = vec![];
+        self.drain_filter_unchecked(&mut predicate, &mut drained);
+        drained
+    }
+
+    fn drain_filter_unchecked<P: FnMut(&U, &T) -> bool>(
+        &mut self,
+        predicate: &mut P,
+        drained: &mut Vec<(U, T)>,
+    ) -> usize {
+        let removed = if self.is_leaf() {
+            let points = self.points.as_mut().unwrap();
+            let bucket = self.bucket.as_mut().unwrap();
+            let mut removed = 0;
+            let mut i = 0;
+            while i < bucket.len() {
+                if predicate(&points[i], &bucket[i]) {
+                    drained.push((points.swap_remove(i), bucket.swap_remove(i)));
+                    removed += 1;
+                } else {
+                    i += 1;
+                }
+            }
+            self.stale_bounds |= removed > 0;
+            removed
+        } else {
+            self.left
+                .as_mut()
+                .unwrap()
+                .drain_filter_unchecked(predicate, drained)
+                + self
+                    .right
+                    .as_mut()
+                    .unwrap()
+                    .drain_filter_unchecked(predicate, drained)
+        };
+        self.size -= removed;
+        removed
+    }
+
+    fn add_to_bucket(&mut self, point: U, data: T) {
+        self.extend_bounds(point.as_ref());
+        let mut points = self.points.take().unwrap();
+        let mut bucket = self.bucket.take().unwrap();
+        points.push(point);
+        bucket.push(data);
         self.size += 1;
-        let next = if self.belongs_in_left(point.as_ref()) {
-            self.left.as_mut()
+        if self.size > self.split_threshold {
+            self.split(points, bucket);
         } else {
-            self.right.as_mut()
+            self.points = Some(points);
+            self.bucket = Some(bucket);
+        }
+    }
+
+    /// Splits a full leaf along its widest dimension. When every point in the
+    /// leaf is identical there is no dimension to split on, so the node stays
+    /// a leaf and keeps growing past `split_threshold`; the next point that
+    /// differs will split it.
+    fn split(&mut self, mut points: Vec<U>, mut bucket: Vec<T>) {
+        // removals leave bounds wider than the points, which could pick a
+        // dimension along which every remaining point is equal; bounds that
+        // are already flat cannot be stale, which saves rescanning a leaf of
+        // identical points on every add. Other bounds are kept, so the box
+        // given to `new_with_bounds` decides the root's first split; such
+        // trees split at midpoints, which cope with a flat dimension
+        self.split_dimension = self.choose_dimension();
+        if self.stale_bounds && self.split_dimension.is_some() {
+            self.min_bounds = B::filled(self.dimensions, A::infinity());
+            self.max_bounds = B::filled(self.dimensions, A::neg_infinity());
+            for point in points.iter() {
+                self.extend_bounds(point.as_ref());
+            }
+            self.split_dimension = self.choose_dimension();
+        }
+        self.stale_bounds = false;
+        match self.split_dimension {
+            None => {
+                self.points = Some(points);
+                self.bucket = Some(bucket);
+                return;
+            }
+            Some(dim) => match self.split_strategy {
+                SplitStrategy::Midpoint => {
+                    let min = self.min_bounds.as_ref()[dim];
+                    let max = self.max_bounds.as_ref()[dim];
+                    let mid = min + (max - min) / A::from(2.0).unwrap();
+                    // between adjacent floats the midpoint rounds to `min`,
+                    // which would send every point to the right
+                    self.split_value = Some(if mid > min { mid } else { max });
+                }
+                SplitStrategy::Median => {
+                    let mut values: Vec<A> = points.iter().map(|p| p.as_ref()[dim]).collect();
+                    self.split_value = Some(util::split_median(&mut values));
+                }
+            },
         };
-        next.unwrap().add_unchecked(point, data)
+        let mut left = self.child();
+        let mut right = self.child();
+        while !points.is_empty() {
+            let point = points.swap_remove(0);
+            let data = bucket.swap_remove(0);
+            if self.belongs_in_left(point.as_ref()) {
+                left.add_to_bucket(point, data);
+            } else {
+                right.add_to_bucket(point, data);
+            }
+        }
+        self.left = Some(left);
+        self.right = Some(right);
+    }
+
+    fn build_unchecked(&mut self, items: Vec<(U, T)>) {
+        if let Some((left_items, right_items)) = self.partition(items) {
+            let mut left = self.child();
+            let mut right = self.child();
+            left.build_unchecked(left_items);
+            right.build_unchecked(right_items);
+            self.left = Some(left);
+            self.right = Some(right);
+        }
+    }
+
+    /// Makes this node a leaf holding `items` if they fit or cannot be split,
+    /// or otherwise a stem splitting them at their median, returning the items
+    /// for its left and right children.
+    #[allow(clippy::type_complexity)]
+    fn partition(&mut self, mut items: Vec<(U, T)>) -> Option<(Vec<(U, T)>, Vec<(U, T)>)> {
+        for (point, _) in items.iter() {
+            self.extend_bounds(point.as_ref());
+        }
+        self.size = items.len();
+        if self.size > self.capacity {
+            self.split_dimension = self.choose_dimension();
+        }
+        let dim = match self.split_dimension {
+            None => {
+                let (points, bucket) = items.into_iter().unzip();
+                self.points = Some(points);
+                self.bucket = Some(bucket);
+                return None;
+            }
+            Some(dim) => dim,
+        };
+        items.sort_unstable_by(|a, b| a.0.as_ref()[dim].partial_cmp(&b.0.as_ref()[dim]).unwrap());
+        // the widest dimension has a non-zero extent, so if the median equals
+        // the minimum there is still a larger value to split on
+        let mut mid = items.len() / 2;
+        let min = items[0].0.as_ref()[dim];
+        if items[mid].0.as_ref()[dim] == min {
+            mid = items.iter().position(|x| x.0.as_ref()[dim] > min).unwrap();
+        }
+        let split_value = items[mid].0.as_ref()[dim];
+        while items[mid - 1].0.as_ref()[dim] == split_value {
+            mid -= 1;
+        }
+        self.split_value = Some(split_value);
+        self.points = None;
+        self.bucket = None;
+        let right_items = items.split_off(mid);
+        Some((items, right_items))
+    }
+
+    fn drain_into(self, items: &mut Vec<(U, T)>) {
+        if self.is_leaf() {
+            let points = self.points.unwrap().into_iter();
+            items.extend(points.zip(self.bucket.unwrap()));
+        } else {
+            self.left.unwrap().drain_into(items);
+            self.right.unwrap().drain_into(items);
+        }
+    }
+
+    /// Returns the dimension to split along, or `None` if the leaf's points
+    /// are all identical.
+    fn choose_dimension(&self) -> Option<usize> {
+        match self.split_axis {
+            SplitAxis::Widest => self.widest_dimension(),
+            SplitAxis::RoundRobin => (0..self.dimensions)
+                .map(|i| (self.level + i) % self.dimensions)
+                .find(|&dim| self.max_bounds.as_ref()[dim] > self.min_bounds.as_ref()[dim]),
+        }
+    }
+
+    fn widest_dimension(&self) -> Option<usize> {
+        let mut max = A::zero();
+        let mut widest = None;
+        for dim in 0..self.dimensions {
+            let low = self.min_bounds.as_ref()[dim];
+            let high = self.max_bounds.as_ref()[dim];
+            // `check_point` keeps non-finite coordinates out of the bounds
+            debug_assert!(low.is_finite() && high.is_finite());
+            let diff = high - low;
+            if diff > max {
+                max = diff;
+                widest = Some(dim);
+            }
+        }
+        widest
+    }
+
+    fn belongs_in_left(&self, point: &[A]) -> bool {
+        point[self.split_dimension.unwrap()] < self.split_value.unwrap()
+    }
+
+    fn extend_bounds(&mut self, point: &[A]) {
+        let min = self.min_bounds.as_mut().iter_mut();
+        let max = self.max_bounds.as_mut().iter_mut();
+        for ((l, h), v) in min.zip(max).zip(point.iter()) {
+            if v < l {
+                *l = *v
+            }
+            if v > h {
+                *h = *v
+            }
+        }
+    }
+
+    fn intersects_bbox(&self, min: &[A], max: &[A]) -> bool {
+        let (lo, hi) = (self.min_bounds.as_ref(), self.max_bounds.as_ref());
+        (0..self.dimensions).all(|i| lo[i] <= max[i] && hi[i] >= min[i])
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.bucket.is_some()
+            && self.points.is_some()
+            && self.split_value.is_none()
+            && self.split_dimension.is_none()
+            && self.left.is_none()
+            && self.right.is_none()
+    }
+
+    fn check_point(&self, point: &[A]) -> Result<(), ErrorKind> {
+        util::check_point(self.dimensions, point)
+    }
+
+    fn check_radius(radius: A) -> Result<(), ErrorKind> {
+        util::check_radius(radius)
+    }
+}
+
+/// Adds every `(point, data)` pair with `add`.
+///
+/// # Panics
+///
+/// Panics on the first pair that `add` rejects, e.g. a point with the wrong
+/// number of dimensions; pairs before it have already been added.
+impl<A: Float + Zero + One, T, U: AsRef<[A]>, B: Bounds<A>> Extend<(U, T)> for KdTree<A, T, U, B> {
+    fn extend<I: IntoIterator<Item = (U, T)>>(&mut self, iter: I) {
+        for (point, data) in iter {
+            if let Err(err) = self.add(point, data) {
+                panic!("{}", err);
+            }
+        }
+    }
+}
+
+/// Compares the stored points and their data, ignoring the order they were
+/// added in and so the shape of the trees: a tree equals its `rebuild`, and
+/// trees with different capacities or split strategies can be equal. Points
+/// are matched by their coordinates, as many times as they are stored.
+impl<A: Float + Zero + One, T: PartialEq, U: AsRef<[A]>, B: Bounds<A>> PartialEq
+    for KdTree<A, T, U, B>
+{
+    fn eq(&self, other: &Self) -> bool {
+        if self.dimensions != other.dimensions || self.size != other.size {
+            return false;
+        }
+        let by_coordinates = |a: &(&U, &T), b: &(&U, &T)| {
+            // stored coordinates are finite, so they are totally ordered
+            a.0.as_ref().partial_cmp(b.0.as_ref()).unwrap()
+        };
+        let mut ours: Vec<_> = self.iter().collect();
+        let mut theirs: Vec<_> = other.iter().collect();
+        ours.sort_unstable_by(by_coordinates);
+        theirs.sort_unstable_by(by_coordinates);
+        let mut used = vec![false; theirs.len()];
+        let mut start = 0;
+        for (point, data) in ours {
+            while start < theirs.len() && theirs[start].0.as_ref() < point.as_ref() {
+                start += 1;
+            }
+            // match the data against the unused points at the same coordinates
+            let mut same =
+                (start..theirs.len()).take_while(|&i| theirs[i].0.as_ref() == point.as_ref());
+            match same.find(|&i| !used[i] && theirs[i].1 == data) {
+                Some(i) => used[i] = true,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Takes every point and its data out of the tree, in no particular order.
+impl<A: Float + Zero + One, T, U: AsRef<[A]>, B: Bounds<A>> IntoIterator for KdTree<A, T, U, B> {
+    type Item = (U, T);
+    type IntoIter = alloc::vec::IntoIter<(U, T)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut items = Vec::with_capacity(self.size);
+        self.drain_into(&mut items);
+        items.into_iter()
+    }
+}
+
+impl<'a, A: Float + Zero + One, T, U: AsRef<[A]>, B: Bounds<A>> IntoIterator
+    for &'a KdTree<A, T, U, B>
+{
+    type Item = (&'a U, &'a T);
+    type IntoIter = Iter<'a, A, T, U, B>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Builds a tree with default capacity whose dimensions are taken from the
+/// first point; an empty iterator gives a zero-dimensional tree.
+///
+/// # Panics
+///
+/// Panics like `Extend` if a later point is rejected by `add`.
+impl<A: Float + Zero + One, T, U: AsRef<[A]>> core::iter::FromIterator<(U, T)> for KdTree<A, T, U> {
+    fn from_iter<I: IntoIterator<Item = (U, T)>>(iter: I) -> Self {
+        let mut iter = iter.into_iter().peekable();
+        let dimensions = iter.peek().map_or(0, |item| item.0.as_ref().len());
+        let mut tree = KdTree::new(dimensions);
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<A: Float + Zero + One, U: AsRef<[A]>, B: Bounds<A>> KdTree<A, usize, U, B> {
+    /// Adds `point` with the next insertion index as its data and returns
+    /// that index. Indices start at zero and are never reused, even after
+    /// points are removed.
+    pub fn add_indexed(&mut self, point: U) -> Result<usize, ErrorKind> {
+        let index = self.next_index;
+        self.add(point, index)?;
+        self.next_index += 1;
+        Ok(index)
+    }
+
+    /// Like `nearest`, but returns the indices assigned by `add_indexed`.
+    pub fn nearest_indices<F>(
+        &self,
+        point: &[A],
+        num: usize,
+        distance: &F,
+    ) -> Result<Vec<(A, usize)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A,
+    {
+        Ok(self
+            .nearest(point, num, distance)?
+            .into_iter()
+            .map(|(dist, &index)| (dist, index))
+            .collect())
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<A, T, U, B> KdTree<A, T, U, B>
+where
+    A: Float + Zero + One + Send + Sync,
+    T: Sync,
+    U: AsRef<[A]> + Sync,
+    B: Bounds<A> + Sync,
+{
+    pub fn nearest_batch<F>(
+        &self,
+        points: &[&[A]],
+        num: usize,
+        distance: &F,
+    ) -> Result<Vec<Vec<(A, &T)>>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A + Sync,
+    {
+        use rayon::prelude::*;
+
+        points
+            .par_iter()
+            .map(|point| self.nearest(point, num, distance))
+            .collect()
+    }
+
+    /// Like `k_distances`, but runs the searches on rayon's thread pool.
+    pub fn k_distances_par<F>(&self, k: usize, distance: &F) -> Result<Vec<(&T, A)>, ErrorKind>
+    where
+        F: Fn(&[A], &[A]) -> A + Sync,
+    {
+        use rayon::prelude::*;
+
+        let points: Vec<_> = self.iter().collect();
+        Ok(points
+            .par_iter()
+            .map(|&(point, data)| (data, self.k_distance(point, k, distance)))
+            .collect())
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<A, T, U> KdTree<A, T, U>
+where
+    A: Float + Zero + One + Send,
+    T: Send,
+    U: AsRef<[A]> + Send,
+{
+    /// Like `build`, but builds the two halves of every large enough subtree
+    /// on separate threads. The tree is the same as `build` would give.
+    pub fn build_par(dimensions: usize, items: Vec<(U, T)>) -> Result<Self, ErrorKind> {
+        KdTree::build_par_with_capacity(dimensions, DEFAULT_CAPACITY, items)
+    }
+
+    pub fn build_par_with_capacity(
+        dimensions: usize,
+        capacity: usize,
+        items: Vec<(U, T)>,
+    ) -> Result<Self, ErrorKind> {
+        KdTree::bulk_load_with(dimensions, capacity, items, KdTree::build_unchecked_par)
+    }
+
+    fn build_unchecked_par(&mut self, items: Vec<(U, T)>) {
+        // below this many points a thread costs more than it saves
+        const PARALLEL_THRESHOLD: usize = 4096;
+
+        if let Some((left_items, right_items)) = self.partition(items) {
+            let mut left = self.child();
+            let mut right = self.child();
+            if self.size > PARALLEL_THRESHOLD {
+                rayon::join(
+                    || left.build_unchecked_par(left_items),
+                    || right.build_unchecked_par(right_items),
+                );
+            } else {
+                left.build_unchecked(left_items);
+                right.build_unchecked(right_items);
+            }
+            self.left = Some(left);
+            self.right = Some(right);
+        }
+    }
+}
+
+/// Pairs the data of every point in `a`, in the order of `a.iter()`, with
+/// its distance to the nearest point in `b` and that point's data. Nothing is
+/// paired when `b` is empty.
+#[allow(clippy::type_complexity)]
+pub fn nearest_between<'a, A, T, S, U, V, B, C, F>(
+    a: &'a KdTree<A, T, U, B>,
+    b: &'a KdTree<A, S, V, C>,
+    distance: &F,
+) -> Result<Vec<(&'a T, A, &'a S)>, ErrorKind>
+where
+    A: Float + Zero + One,
+    U: AsRef<[A]>,
+    V: AsRef<[A]>,
+    B: Bounds<A>,
+    C: Bounds<A>,
+    F: Fn(&[A], &[A]) -> A,
+{
+    let mut pairs = Vec::with_capacity(a.size());
+    for (point, data) in a.iter() {
+        if let Some((dist, nearest)) = b.nearest_one(point.as_ref(), distance)? {
+            pairs.push((data, dist, nearest));
+        }
+    }
+    Ok(pairs)
+}
+
+/// Like `nearest_between`, but queries `b` for the points of `a` in parallel.
+#[cfg(feature = "rayon")]
+#[allow(clippy::type_complexity)]
+pub fn nearest_between_par<'a, A, T, S, U, V, B, C, F>(
+    a: &'a KdTree<A, T, U, B>,
+    b: &'a KdTree<A, S, V, C>,
+    distance: &F,
+) -> Result<Vec<(&'a T, A, &'a S)>, ErrorKind>
+where
+    A: Float + Zero + One + Send + Sync,
+    T: Sync,
+    S: Sync,
+    U: AsRef<[A]> + Sync,
+    V: AsRef<[A]> + Sync,
+    B: Bounds<A> + Sync,
+    C: Bounds<A> + Sync,
+    F: Fn(&[A], &[A]) -> A + Sync,
+{
+    use rayon::prelude::*;
+
+    let points: Vec<_> = a.iter().collect();
+    let nearest = points
+        .par_iter()
+        .map(|&(point, data)| {
+            let found = b.nearest_one(point.as_ref(), distance)?;
+            Ok(found.map(|(dist, nearest)| (data, dist, nearest)))
+        })
+        .collect::<Result<Vec<_>, ErrorKind>>()?;
+    Ok(nearest.into_iter().flatten().collect())
+}
+
+#[cfg(feature = "persist")]
+impl<A, T, U, B> KdTree<A, T, U, B>
+where
+    U: AsRef<[A]>,
+    Self: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the tree to the file at `path` in bincode format, replacing
+    /// the file if it exists.
+    pub fn save_to_path<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        bincode::serialize_into(file, self).map_err(|err| bincode_to_io(*err))
+    }
+
+    /// Reads a tree written by `save_to_path`.
+    pub fn load_from_path<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        bincode::deserialize_from(file).map_err(|err| bincode_to_io(*err))
     }
+}
 
-    fn add_to_bucket(&mut self, point: U, data: T) {
-        self.extend(point.as_ref());
-        let mut points = self.points.take().unwrap();
-        let mut bucket = self.bucket.take().unwrap();
-        points.push(point);
-        bucket.push(data);
-        self.size += 1;
-        if self.size > self.capacity {
-            self.split(points, bucket);
-        } else {
-            self.points = Some(points);
-            self.bucket = Some(bucket);
-        }
+#[cfg(feature = "persist")]
+fn bincode_to_io(err: bincode::ErrorKind) -> std::io::Error {
+    match err {
+        bincode::ErrorKind::Io(err) => err,
+        err => std::io::Error::new(std::io::ErrorKind::InvalidData, err),
     }
+}
 
-    fn split(&mut self, mut points: Vec<U>, mut bucket: Vec<T>) {
-        let mut max = A::zero();
-        for dim in 0..self.dimensions {
-            let diff = self.max_bounds[dim] - self.min_bounds[dim];
-            if !diff.is_nan() && diff > max {
-                max = diff;
-                self.split_dimension = Some(dim);
-            }
-        }
-        match self.split_dimension {
-            None => {
-                self.points = Some(points);
-                self.bucket = Some(bucket);
-                return;
+#[cfg(not(all(feature = "no_std", not(test))))]
+impl<A, T, U, B> KdTree<A, T, U, B>
+where
+    A: Float + Zero + One + core::fmt::Display,
+    U: AsRef<[A]>,
+    B: Bounds<A>,
+{
+    /// Writes the coordinates of every point as CSV, one line per point in
+    /// `iter()` order, without a header and without the data. Floats are
+    /// written in their shortest exact form, so `from_csv` reads back the
+    /// same coordinates.
+    pub fn to_csv<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
+        for (point, _) in self.iter() {
+            let mut values = point.as_ref().iter();
+            if let Some(first) = values.next() {
+                write!(w, "{}", first)?;
             }
-            Some(dim) => {
-                let min = self.min_bounds[dim];
-                let max = self.max_bounds[dim];
-                self.split_value = Some(min + (max - min) / A::from(2.0).unwrap());
+            for value in values {
+                write!(w, ",{}", value)?;
             }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(all(feature = "no_std", not(test))))]
+impl<A: Float + Zero + One + core::str::FromStr> KdTree<A, (), Vec<A>> {
+    /// Builds a tree from CSV written by `to_csv`, or any other with
+    /// `dimensions` numbers per line and no header. Blank lines are skipped.
+    /// A line with the wrong number of columns, or with a value that does not
+    /// parse or is not finite, fails with an `InvalidData` error naming the
+    /// line.
+    pub fn from_csv<R: std::io::BufRead>(dimensions: usize, r: R) -> std::io::Result<Self> {
+        let invalid = |line: usize, message: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}: {}", line + 1, message),
+            )
         };
-        let mut left = Box::new(KdTree::with_capacity(self.dimensions, self.capacity));
-        let mut right = Box::new(KdTree::with_capacity(self.dimensions, self.capacity));
-        while !points.is_empty() {
-            let point = points.swap_remove(0);
-            let data = bucket.swap_remove(0);
-            if self.belongs_in_left(point.as_ref()) {
-                left.add_to_bucket(point, data);
-            } else {
-                right.add_to_bucket(point, data);
+        let mut items = vec![];
+        for (number, line) in r.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
             }
+            let point = line
+                .split(',')
+                .map(|value| value.trim().parse::<A>())
+                .collect::<Result<Vec<A>, _>>()
+                .map_err(|_| invalid(number, format!("cannot parse {:?}", line)))?;
+            if point.len() != dimensions {
+                let message = format!("expected {} columns, found {}", dimensions, point.len());
+                return Err(invalid(number, message));
+            }
+            util::check_point(dimensions, &point)
+                .map_err(|err| invalid(number, err.to_string()))?;
+            items.push((point, ()));
         }
-        self.left = Some(left);
-        self.right = Some(right);
+        KdTree::build(dimensions, items)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
     }
+}
 
-    fn belongs_in_left(&self, point: &[A]) -> bool {
-        point[self.split_dimension.unwrap()] < self.split_value.unwrap()
+/// A point found by a query, as returned by `nearest_neighbors`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Neighbor<'a, A, T: 'a> {
+    /// The distance from the query, as returned by the distance function.
+    pub distance: A,
+    pub data: &'a T,
+}
+
+impl<'a, A, T> From<(A, &'a T)> for Neighbor<'a, A, T> {
+    fn from((distance, data): (A, &'a T)) -> Self {
+        Neighbor { distance, data }
     }
+}
 
-    fn extend(&mut self, point: &[A]) {
-        let min = self.min_bounds.iter_mut();
-        let max = self.max_bounds.iter_mut();
-        for ((l, h), v) in min.zip(max).zip(point.iter()) {
-            if v < l {
-                *l = *v
-            }
-            if v > h {
-                *h = *v
-            }
+impl<'a, A, T> From<Neighbor<'a, A, T>> for (A, &'a T) {
+    fn from(neighbor: Neighbor<'a, A, T>) -> Self {
+        (neighbor.distance, neighbor.data)
+    }
+}
+
+/// Counts of the work done by a `nearest_with_stats` query.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueryStats {
+    /// Nodes descended through, stems and leaves alike.
+    pub nodes_visited: usize,
+    pub leaves_visited: usize,
+    /// Calls made to the distance function for stored points.
+    pub points_evaluated: usize,
+}
+
+trait Recorder {
+    fn node(&mut self);
+    fn leaf(&mut self);
+    fn point(&mut self);
+}
+
+impl Recorder for () {
+    fn node(&mut self) {}
+    fn leaf(&mut self) {}
+    fn point(&mut self) {}
+}
+
+impl Recorder for QueryStats {
+    fn node(&mut self) {
+        self.nodes_visited += 1;
+    }
+
+    fn leaf(&mut self) {
+        self.leaves_visited += 1;
+    }
+
+    fn point(&mut self) {
+        self.points_evaluated += 1;
+    }
+}
+
+type Pending<'a, A, T, U, B> = BinaryHeap<HeapElement<A, &'a KdTree<A, T, U, B>>>;
+type PendingMut<'a, A, T, U, B> = BinaryHeap<HeapElement<A, &'a mut KdTree<A, T, U, B>>>;
+
+pub struct QueryScratch<'a, A: 'a, T: 'a, U: 'a + AsRef<[A]>, B: 'a = Box<[A]>> {
+    pending: Pending<'a, A, T, U, B>,
+    evaluated: BinaryHeap<HeapElement<A, &'a T>>,
+}
+
+impl<'a, A: Float, T, U: AsRef<[A]>, B> QueryScratch<'a, A, T, U, B> {
+    pub fn new() -> Self {
+        QueryScratch {
+            pending: BinaryHeap::new(),
+            evaluated: BinaryHeap::new(),
         }
     }
+}
 
-    fn is_leaf(&self) -> bool {
-        self.bucket.is_some()
-            && self.points.is_some()
-            && self.split_value.is_none()
-            && self.split_dimension.is_none()
-            && self.left.is_none()
-            && self.right.is_none()
+impl<'a, A: Float, T, U: AsRef<[A]>, B> Default for QueryScratch<'a, A, T, U, B> {
+    fn default() -> Self {
+        QueryScratch::new()
     }
+}
 
-    fn check_point(&self, point: &[A]) -> Result<(), ErrorKind> {
-        if self.dimensions != point.len() {
-            return Err(ErrorKind::WrongDimension);
+/// A point that may or may not be stored yet, from `KdTree::entry`.
+pub struct Entry<'a, A: 'a, T: 'a, U: 'a + AsRef<[A]>, B: 'a = Box<[A]>> {
+    tree: &'a mut KdTree<A, T, U, B>,
+    point: U,
+}
+
+impl<'a, A: Float + Zero + One, T: 'a, U: 'a + AsRef<[A]>, B: 'a + Bounds<A>>
+    Entry<'a, A, T, U, B>
+{
+    /// Returns the data stored at the point, adding the point with `default`
+    /// first if it is not stored.
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        self.or_insert_with(|| default)
+    }
+
+    /// Like `or_insert`, but only calls `default` when the point is added.
+    pub fn or_insert_with<F: FnOnce() -> T>(self, default: F) -> &'a mut T {
+        if self.tree.contains(self.point.as_ref()) == Ok(true) {
+            return self.tree.exact_mut(self.point.as_ref()).unwrap();
+        }
+        let coordinates = self.point.as_ref().to_vec();
+        // the point was checked by `entry`
+        self.tree.add_unchecked(self.point, default()).unwrap();
+        self.tree.exact_mut(&coordinates).unwrap()
+    }
+}
+
+type LeafIter<'a, T, U> = core::iter::Zip<core::slice::Iter<'a, U>, core::slice::Iter<'a, T>>;
+
+pub struct Leaves<'a, A: 'a, T: 'a, U: 'a + AsRef<[A]>, B: 'a = Box<[A]>> {
+    pending: Vec<&'a KdTree<A, T, U, B>>,
+}
+
+impl<'a, A: Float + Zero + One, T: 'a, U: 'a + AsRef<[A]>, B: 'a + Bounds<A>> Iterator
+    for Leaves<'a, A, T, U, B>
+{
+    type Item = (&'a [U], &'a [T]);
+
+    fn next(&mut self) -> Option<(&'a [U], &'a [T])> {
+        loop {
+            let curr = self.pending.pop()?;
+            if curr.is_leaf() {
+                let points = curr.points.as_ref().unwrap();
+                let bucket = curr.bucket.as_ref().unwrap();
+                return Some((points, bucket));
+            }
+            self.pending.push(curr.right.as_ref().unwrap());
+            self.pending.push(curr.left.as_ref().unwrap());
         }
-        for n in point {
-            if !n.is_finite() {
-                return Err(ErrorKind::NonFiniteCoordinate);
+    }
+}
+
+pub struct Iter<'a, A: 'a, T: 'a, U: 'a + AsRef<[A]>, B: 'a = Box<[A]>> {
+    leaves: Leaves<'a, A, T, U, B>,
+    leaf: Option<LeafIter<'a, T, U>>,
+}
+
+impl<'a, A: Float + Zero + One, T: 'a, U: 'a + AsRef<[A]>, B: 'a + Bounds<A>> Iterator
+    for Iter<'a, A, T, U, B>
+{
+    type Item = (&'a U, &'a T);
+
+    fn next(&mut self) -> Option<(&'a U, &'a T)> {
+        loop {
+            if let Some(item) = self.leaf.as_mut().and_then(Iterator::next) {
+                return Some(item);
             }
+            let (points, bucket) = self.leaves.next()?;
+            self.leaf = Some(points.iter().zip(bucket.iter()));
         }
-        Ok(())
     }
 }
 
@@ -370,15 +3241,16 @@ pub struct NearestIter<
     T: 'b,
     U: 'b + AsRef<[A]>,
     F: 'a + Fn(&[A], &[A]) -> A,
+    B: 'b = Box<[A]>,
 > {
     point: &'a [A],
-    pending: BinaryHeap<HeapElement<A, &'b KdTree<A, T, U>>>,
+    pending: Pending<'b, A, T, U, B>,
     evaluated: BinaryHeap<HeapElement<A, &'b T>>,
     distance: &'a F,
 }
 
-impl<'a, 'b, A: Float + Zero + One, T: 'b, U: 'b + AsRef<[A]>, F: 'a> Iterator
-    for NearestIter<'a, 'b, A, T, U, F>
+impl<'a, 'b, A: Float + Zero + One, T: 'b, U: 'b + AsRef<[A]>, F: 'a, B: 'b + Bounds<A>> Iterator
+    for NearestIter<'a, 'b, A, T, U, F, B>
 where
     F: Fn(&[A], &[A]) -> A,
 {
@@ -392,7 +3264,7 @@ where
             && (self.evaluated.peek().map_or(A::infinity(), |x| -x.distance)
                 >= -self.pending.peek().unwrap().distance)
         {
-            let mut curr = &*self.pending.pop().unwrap().element;
+            let mut curr = self.pending.pop().unwrap().element;
             while !curr.is_leaf() {
                 let candidate;
                 if curr.belongs_in_left(point) {
@@ -405,8 +3277,8 @@ where
                 self.pending.push(HeapElement {
                     distance: -distance_to_space(
                         point,
-                        &*candidate.min_bounds,
-                        &*candidate.max_bounds,
+                        candidate.min_bounds.as_ref(),
+                        candidate.max_bounds.as_ref(),
                         distance,
                     ),
                     element: &**candidate,
@@ -424,6 +3296,30 @@ where
     }
 }
 
+pub struct NearestCursor<
+    'a,
+    'b,
+    A: 'a + 'b + Float,
+    T: 'b,
+    U: 'b + AsRef<[A]>,
+    F: 'a + Fn(&[A], &[A]) -> A,
+    B: 'b = Box<[A]>,
+> {
+    iter: NearestIter<'a, 'b, A, T, U, F, B>,
+}
+
+impl<'a, 'b, A: Float + Zero + One, T: 'b, U: 'b + AsRef<[A]>, F: 'a, B: 'b + Bounds<A>>
+    NearestCursor<'a, 'b, A, T, U, F, B>
+where
+    F: Fn(&[A], &[A]) -> A,
+{
+    /// Returns the next `num` nearest points, or fewer once the tree runs
+    /// out; an empty batch means every point has been returned.
+    pub fn next_batch(&mut self, num: usize) -> Vec<(A, &'b T)> {
+        self.iter.by_ref().take(num).collect()
+    }
+}
+
 pub struct NearestIterMut<
     'a,
     'b,
@@ -431,15 +3327,16 @@ pub struct NearestIterMut<
     T: 'b,
     U: 'b + AsRef<[A]>,
     F: 'a + Fn(&[A], &[A]) -> A,
+    B: 'b = Box<[A]>,
 > {
     point: &'a [A],
-    pending: BinaryHeap<HeapElement<A, &'b mut KdTree<A, T, U>>>,
+    pending: PendingMut<'b, A, T, U, B>,
     evaluated: BinaryHeap<HeapElement<A, &'b mut T>>,
     distance: &'a F,
 }
 
-impl<'a, 'b, A: Float + Zero + One, T: 'b, U: 'b + AsRef<[A]>, F: 'a> Iterator
-    for NearestIterMut<'a, 'b, A, T, U, F>
+impl<'a, 'b, A: Float + Zero + One, T: 'b, U: 'b + AsRef<[A]>, F: 'a, B: 'b + Bounds<A>> Iterator
+    for NearestIterMut<'a, 'b, A, T, U, F, B>
 where
     F: Fn(&[A], &[A]) -> A,
 {
@@ -466,8 +3363,8 @@ where
                 self.pending.push(HeapElement {
                     distance: -distance_to_space(
                         point,
-                        &*candidate.min_bounds,
-                        &*candidate.max_bounds,
+                        candidate.min_bounds.as_ref(),
+                        candidate.max_bounds.as_ref(),
                         distance,
                     ),
                     element: &mut **candidate,
@@ -485,27 +3382,73 @@ where
     }
 }
 
-impl std::error::Error for ErrorKind {
-    fn description(&self) -> &str {
-        match *self {
-            ErrorKind::WrongDimension => "wrong dimension",
-            ErrorKind::NonFiniteCoordinate => "non-finite coordinate",
-            ErrorKind::ZeroCapacity => "zero capacity",
+#[cfg(not(all(feature = "no_std", not(test))))]
+impl std::error::Error for ErrorKind {}
+
+impl<A: Float + Zero + One, T, U: AsRef<[A]>, B: Bounds<A>> core::fmt::Debug
+    for KdTree<A, T, U, B>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("KdTree")
+            .field("dimensions", &self.dimensions)
+            .field("size", &self.size)
+            .field("depth", &self.depth())
+            .field("leaves", &self.leaf_count())
+            .finish()
+    }
+}
+
+struct TreeDump<'a, A: 'a, T: 'a, U: 'a + AsRef<[A]>, B: 'a>(&'a KdTree<A, T, U, B>);
+
+impl<'a, A, T, U, B> core::fmt::Debug for TreeDump<'a, A, T, U, B>
+where
+    A: Float + Zero + One + core::fmt::Debug,
+    T: core::fmt::Debug,
+    U: AsRef<[A]> + core::fmt::Debug,
+    B: Bounds<A>,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let node = self.0;
+        if node.is_leaf() {
+            return f
+                .debug_struct("Leaf")
+                .field("min_bounds", &node.min_bounds.as_ref())
+                .field("max_bounds", &node.max_bounds.as_ref())
+                .field("points", node.points.as_ref().unwrap())
+                .field("bucket", node.bucket.as_ref().unwrap())
+                .finish();
         }
+        f.debug_struct("Stem")
+            .field("size", &node.size)
+            .field("min_bounds", &node.min_bounds.as_ref())
+            .field("max_bounds", &node.max_bounds.as_ref())
+            .field("split_dimension", &node.split_dimension.unwrap())
+            .field("split_value", &node.split_value.unwrap())
+            .field("left", &TreeDump(&**node.left.as_ref().unwrap()))
+            .field("right", &TreeDump(&**node.right.as_ref().unwrap()))
+            .finish()
     }
 }
 
-impl std::fmt::Display for ErrorKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        use std::error::Error;
-        write!(f, "KdTree error: {}", self.description())
+impl core::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let reason = match *self {
+            ErrorKind::WrongDimension => "point has wrong number of dimensions",
+            ErrorKind::NonFiniteCoordinate => "coordinate is not finite",
+            ErrorKind::ZeroCapacity => "capacity must be non-zero",
+            ErrorKind::InvalidBounds => "minimum bound is greater than maximum bound",
+            ErrorKind::InvalidRadius => "radius is negative or NaN",
+            ErrorKind::NoPoints => "no points to compute a result from",
+        };
+        write!(f, "KdTree error: {}", reason)
     }
 }
 
 #[cfg(test)]
 mod tests {
     extern crate rand;
-    use super::KdTree;
+    use super::{KdTree, KdTreeBuilder, KdTreeN, SplitAxis, SplitStrategy, DEFAULT_CAPACITY};
+    use crate::distance::squared_euclidean;
 
     fn random_point() -> ([f64; 2], i32) {
         rand::random::<([f64; 2], i32)>()
@@ -526,6 +3469,115 @@ mod tests {
         cloned_tree.add(pos, data).unwrap();
         assert_eq!(tree.size(), 1);
         assert_eq!(cloned_tree.size(), 2);
+
+        for _ in 0..40 {
+            let (pos, data) = random_point();
+            cloned_tree.add(pos, data).unwrap();
+        }
+        assert_eq!(tree.size(), 1);
+        assert!(tree.is_leaf());
+        assert_eq!(
+            tree.nearest(&pos, 5, &squared_euclidean).unwrap(),
+            vec![(0f64, &data)]
+        );
+    }
+
+    #[test]
+    fn it_cycles_split_axes_by_depth() {
+        let build = |split_axis| {
+            let mut tree = KdTreeBuilder::new()
+                .dimensions(2)
+                .capacity(4)
+                .split_axis(split_axis)
+                .build()
+                .unwrap();
+            for i in 0..256 {
+                tree.add([i as f64 * 100.0, (i * 7 % 10) as f64], i)
+                    .unwrap();
+            }
+            // the median splits of a rebuild see every point at once
+            tree.rebuild();
+            tree
+        };
+        let count_axes = |tree: &KdTree<f64, usize, [f64; 2]>| {
+            let mut counts = [0, 0];
+            let mut stems = vec![tree];
+            while let Some(node) = stems.pop() {
+                if let Some(dim) = node.split_dimension {
+                    counts[dim] += 1;
+                    stems.push(node.left.as_ref().unwrap());
+                    stems.push(node.right.as_ref().unwrap());
+                }
+            }
+            counts
+        };
+
+        let widest = build(SplitAxis::Widest);
+        let round_robin = build(SplitAxis::RoundRobin);
+        let widest_counts = count_axes(&widest);
+        let round_robin_counts = count_axes(&round_robin);
+        assert_eq!(widest_counts[1], 0);
+        assert!(round_robin_counts[1] * 2 > round_robin_counts[0]);
+        assert_eq!(round_robin.split_dimension, Some(0));
+        assert_eq!(round_robin.left.as_ref().unwrap().split_dimension, Some(1));
+
+        for &query in [[1234.0, 3.0], [0.0, 9.0], [25600.0, 0.0]].iter() {
+            assert_eq!(
+                round_robin.nearest(&query, 5, &squared_euclidean).unwrap(),
+                widest.nearest(&query, 5, &squared_euclidean).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn it_validates_its_invariants() {
+        let mut tree: KdTree<f64, usize, [f64; 2]> = KdTree::with_capacity(2, 4);
+        assert_eq!(tree.validate(), Ok(()));
+        for i in 0..100 {
+            let (pos, _) = random_point();
+            tree.add(pos, i).unwrap();
+        }
+        for _ in 0..10 {
+            tree.remove_nearest(&[0.5, 0.5], &squared_euclidean)
+                .unwrap();
+        }
+        assert_eq!(tree.validate(), Ok(()));
+        tree.rebuild();
+        assert_eq!(tree.validate(), Ok(()));
+
+        let mut broken = tree.clone();
+        broken.size += 1;
+        assert!(broken.validate().unwrap_err().starts_with("root: has size"));
+
+        let mut broken = tree.clone();
+        let left_value = broken.left.as_mut().unwrap().split_value.take();
+        assert!(broken
+            .validate()
+            .unwrap_err()
+            .starts_with("root.left: is neither"));
+        broken.left.as_mut().unwrap().split_value = left_value;
+        broken.split_value = Some(broken.split_value.unwrap() - 100.0);
+        assert!(broken
+            .validate()
+            .unwrap_err()
+            .starts_with("root.left: holds a point"));
+
+        let mut broken = tree.clone();
+        let right = broken.right.as_mut().unwrap();
+        right.min_bounds[0] = right.max_bounds[0];
+        assert!(broken
+            .validate()
+            .unwrap_err()
+            .starts_with("root.right: holds a point outside"));
+    }
+
+    #[test]
+    fn it_has_a_default_when_its_dimensions_are_fixed() {
+        let mut tree: KdTreeN<f64, i32, [f64; 3], 3> = Default::default();
+        assert_eq!(tree.dimensions, 3);
+        assert_eq!(tree.capacity, DEFAULT_CAPACITY);
+        tree.add([1.0, 2.0, 3.0], 0).unwrap();
+        assert_eq!(tree.size(), 1);
     }
 
     #[test]
@@ -548,6 +3600,318 @@ mod tests {
         assert!(tree.left.is_some() && tree.right.is_some());
     }
 
+    #[test]
+    fn it_builds_a_balanced_tree_from_sorted_input() {
+        let items = (0..1024).map(|i| ([i as f64, 0f64], i)).collect::<Vec<_>>();
+        let tree = KdTree::build_with_capacity(2, 16, items.clone()).unwrap();
+        assert_eq!(tree.size(), 1024);
+        assert_eq!(tree.depth(), 6);
+
+        let mut added = KdTree::with_capacity(2, 16);
+        for (pos, data) in items {
+            added.add(pos, data).unwrap();
+        }
+        assert!(added.depth() > 2 * tree.depth());
+    }
+
+    #[test]
+    fn it_can_be_rebuilt() {
+        let mut tree = KdTree::with_capacity(1, 2);
+        for i in 0..256 {
+            tree.add([i as f64], i).unwrap();
+        }
+        let deep = tree.depth();
+        let before = tree.nearest(&[99.7], 4, &squared_euclidean).unwrap();
+        let before: Vec<_> = before.into_iter().map(|(d, &i)| (d, i)).collect();
+
+        tree.rebuild();
+        assert!(tree.depth() * 4 <= deep);
+        assert_eq!(tree.size(), 256);
+        let after = tree.nearest(&[99.7], 4, &squared_euclidean).unwrap();
+        let after: Vec<_> = after.into_iter().map(|(d, &i)| (d, i)).collect();
+        assert_eq!(after, before);
+
+        tree.retain(|&i| i < 10);
+        tree.rebuild();
+        assert_eq!(tree.size(), 10);
+        assert_eq!(&*tree.max_bounds, &[9.0]);
+
+        tree.clear();
+        tree.rebuild();
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn it_builds_around_repeated_medians() {
+        let mut items = vec![([0f64], 0); 20];
+        items.push(([1f64], 1));
+        let tree = KdTree::build_with_capacity(1, 4, items).unwrap();
+        assert_eq!(tree.size(), 21);
+        assert_eq!(tree.split_value, Some(1f64));
+        assert_eq!(tree.left.as_ref().unwrap().size(), 20);
+        assert_eq!(tree.right.as_ref().unwrap().size(), 1);
+    }
+
+    #[test]
+    fn it_can_be_shared_between_threads() {
+        fn assert_send_sync<S: Send + Sync>() {}
+        fn _assert_send_sync<T: Send + Sync, U: Send + Sync + AsRef<[f64]>>() {
+            assert_send_sync::<KdTree<f64, T, U>>();
+            assert_send_sync::<KdTreeN<f64, T, U, 3>>();
+        }
+        _assert_send_sync::<i32, [f64; 2]>();
+        _assert_send_sync::<String, Vec<f64>>();
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn it_can_be_serialized() {
+        fn assert_serde<S: serde::Serialize + serde::de::DeserializeOwned>() {}
+        assert_serde::<KdTree<f64, i32, [f64; 2]>>();
+        assert_serde::<KdTree<f32, String, Vec<f32>>>();
+
+        let mut tree = KdTree::with_capacity(2, 2);
+        for i in 0..50 {
+            tree.add([(i * 7 % 13) as f64, (i * 5 % 11) as f64], i)
+                .unwrap();
+        }
+        // leaves bounds wider than the points, including infinite ones
+        tree.remove(&[0.0, 0.0], &0).unwrap();
+        let bytes = bincode::serialize(&tree).unwrap();
+        let loaded: KdTree<f64, i32, [f64; 2]> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.size(), tree.size());
+        assert_eq!(loaded.depth(), tree.depth());
+        for query in [[0.0, 0.0], [6.5, 4.0], [-3.0, 20.0]].iter() {
+            assert_eq!(
+                loaded.nearest(query, 5, &squared_euclidean).unwrap(),
+                tree.nearest(query, 5, &squared_euclidean).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn it_splits_a_seeded_root_on_its_box() {
+        let mut tree: KdTree<f64, usize, [f64; 2]> =
+            KdTree::new_with_bounds(2, 2, &[0.0, 0.0], &[1.0, 2.0]).unwrap();
+        for (i, &point) in [[0.1, 0.1], [0.2, 0.3], [0.3, 0.2]].iter().enumerate() {
+            tree.add(point, i).unwrap();
+        }
+        // the points alone would split along x at 0.2
+        assert_eq!(
+            (tree.split_dimension, tree.split_value),
+            (Some(1), Some(1.0))
+        );
+        assert_eq!(tree.min_bounds(), &[0.0, 0.0]);
+        assert_eq!(tree.max_bounds(), &[1.0, 2.0]);
+        assert_eq!(tree.left.as_ref().unwrap().size(), 3);
+        assert!(tree.validate().is_ok());
+
+        // removals make the next split look at the points again
+        let mut tree: KdTree<f64, usize, [f64; 1]> =
+            KdTree::new_with_bounds(1, 2, &[0.0], &[100.0]).unwrap();
+        tree.add([0.0], 0).unwrap();
+        tree.add([10.0], 1).unwrap();
+        tree.remove(&[0.0], &0).unwrap();
+        tree.add([10.0], 2).unwrap();
+        tree.add([12.0], 3).unwrap();
+        assert_eq!(tree.split_value, Some(11.0));
+    }
+
+    #[test]
+    fn it_counts_indices_at_the_root() {
+        let mut tree: KdTree<f64, usize, [f64; 1]> = KdTree::with_capacity(1, 2);
+        for i in 0..20 {
+            assert_eq!(tree.add_indexed([(i * 7 % 20) as f64]).unwrap(), i);
+        }
+        assert_eq!(tree.next_index, 20);
+        let mut nodes = vec![tree.left.as_ref().unwrap(), tree.right.as_ref().unwrap()];
+        while let Some(node) = nodes.pop() {
+            assert_eq!(node.next_index, 0);
+            if !node.is_leaf() {
+                nodes.push(node.left.as_ref().unwrap());
+                nodes.push(node.right.as_ref().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn it_splits_skewed_input_at_the_median() {
+        let add_skewed = |tree: &mut KdTree<f64, usize, [f64; 1]>| {
+            for i in 0..40 {
+                let i = i * 7 % 40;
+                tree.add([2f64.powi(i as i32)], i).unwrap();
+            }
+        };
+        let mut midpoint = KdTree::with_capacity(1, 4);
+        add_skewed(&mut midpoint);
+        let mut median = KdTree::new_with_strategy(1, SplitStrategy::Median);
+        median.capacity = 4;
+        median.split_threshold = 4;
+        add_skewed(&mut median);
+
+        assert_eq!(median.size(), 40);
+        assert!(median.depth() < midpoint.depth());
+        let mut stems = vec![&median];
+        while let Some(node) = stems.pop() {
+            if node.is_leaf() {
+                continue;
+            }
+            let left = node.left.as_ref().unwrap();
+            let right = node.right.as_ref().unwrap();
+            assert!(left.size() > 0 && right.size() > 0);
+            stems.push(left);
+            stems.push(right);
+        }
+        let nearest = median.nearest(&[3.0], 2, &squared_euclidean).unwrap();
+        assert_eq!(nearest, vec![(1.0, &1), (1.0, &2)]);
+    }
+
+    #[test]
+    fn it_can_be_cleared() {
+        let mut tree: KdTree<f64, i32, [f64; 2]> = KdTree::with_capacity(2, 4);
+        for _ in 0..20 {
+            let (pos, data) = random_point();
+            tree.add(pos, data).unwrap();
+        }
+        assert!(!tree.is_leaf());
+
+        tree.clear();
+        assert!(tree.is_leaf());
+        assert_eq!(tree.size(), 0);
+        assert_eq!(tree.dimensions, 2);
+        assert_eq!(tree.capacity, 4);
+        assert!(tree.min_bounds.iter().all(|&b| b == f64::INFINITY));
+        assert!(tree.max_bounds.iter().all(|&b| b == f64::NEG_INFINITY));
+
+        tree.add([1.0, 2.0], 3).unwrap();
+        assert_eq!(tree.size(), 1);
+        assert_eq!(&*tree.min_bounds, &[1.0, 2.0]);
+        assert_eq!(&*tree.max_bounds, &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn it_estimates_its_memory_usage() {
+        use std::mem::size_of;
+
+        let mut tree: KdTree<f64, i32, [f64; 2]> = KdTree::with_capacity(2, 4);
+        let node = size_of::<KdTree<f64, i32, [f64; 2]>>() + 2 * 2 * size_of::<f64>();
+        assert_eq!(tree.memory_usage(), node);
+
+        tree.points.as_mut().unwrap().reserve_exact(3);
+        tree.bucket.as_mut().unwrap().reserve_exact(3);
+        assert_eq!(
+            tree.memory_usage(),
+            node + 3 * size_of::<[f64; 2]>() + 3 * size_of::<i32>()
+        );
+
+        for _ in 0..20 {
+            let (pos, data) = random_point();
+            tree.add(pos, data).unwrap();
+        }
+        assert!(tree.memory_usage() >= tree.node_count() * node + 20 * (16 + 4));
+    }
+
+    #[test]
+    fn it_takes_a_capacity_hint() {
+        let tree: KdTree<f64, i32, [f64; 2]> = KdTree::with_capacity_hint(2, 64, 10);
+        assert!(tree.points.as_ref().unwrap().capacity() >= 10);
+        assert!(tree.points.as_ref().unwrap().capacity() < 64);
+
+        let mut tree: KdTree<f64, i32, [f64; 2]> = KdTree::with_capacity_hint(2, 64, 1_000_000);
+        assert!(tree.points.as_ref().unwrap().capacity() >= 65);
+        assert!(tree.bucket.as_ref().unwrap().capacity() < 1000);
+        for _ in 0..1000 {
+            let (pos, data) = random_point();
+            tree.add(pos, data).unwrap();
+        }
+        assert_eq!(tree.size(), 1000);
+        assert!(!tree.is_leaf());
+    }
+
+    #[test]
+    fn it_can_reserve_room_before_splitting() {
+        let mut tree: KdTree<f64, i32, [f64; 2]> = KdTree::with_capacity(2, 64);
+        tree.reserve(64);
+        assert!(tree.points.as_ref().unwrap().capacity() >= 64);
+        assert!(tree.bucket.as_ref().unwrap().capacity() >= 64);
+
+        for _ in 0..65 {
+            let (pos, data) = random_point();
+            tree.add(pos, data).unwrap();
+        }
+        let before = tree.memory_usage();
+        tree.reserve(1000);
+        assert_eq!(tree.memory_usage(), before);
+    }
+
+    #[test]
+    fn it_can_shrink_its_leaves() {
+        let mut tree: KdTree<f64, i32, [f64; 2]> = KdTree::with_capacity(2, 4);
+        for _ in 0..20 {
+            let (pos, data) = random_point();
+            tree.add(pos, data).unwrap();
+        }
+        let mut leaf = &mut tree;
+        while !leaf.is_leaf() {
+            leaf = leaf.left.as_mut().unwrap();
+        }
+        leaf.points.as_mut().unwrap().reserve(100);
+        leaf.bucket.as_mut().unwrap().reserve(100);
+        let before = tree.memory_usage();
+
+        tree.shrink_to_fit();
+        assert!(tree.memory_usage() < before);
+        let mut leaf = &tree;
+        while !leaf.is_leaf() {
+            leaf = leaf.left.as_ref().unwrap();
+        }
+        let points = leaf.points.as_ref().unwrap();
+        let bucket = leaf.bucket.as_ref().unwrap();
+        assert!(points.capacity() < 100);
+        assert_eq!(points.len(), bucket.len());
+        assert!(bucket.capacity() < 100);
+        assert_eq!(tree.size(), 20);
+    }
+
+    #[test]
+    fn it_can_be_compacted() {
+        let mut tree: KdTree<f64, usize, [f64; 1]> = KdTree::with_capacity(1, 4);
+        for i in 0..64 {
+            tree.add([i as f64], i).unwrap();
+        }
+        let nodes = tree.node_count();
+        tree.retain(|&i| i % 8 == 0 || i >= 60);
+        assert_eq!(tree.size(), 12);
+
+        tree.compact();
+        assert!(tree.node_count() < nodes);
+        assert_eq!(tree.size(), 12);
+        let mut found: Vec<_> = tree.iter().map(|(_, &i)| i).collect();
+        found.sort_unstable();
+        assert_eq!(found, vec![0, 8, 16, 24, 32, 40, 48, 56, 60, 61, 62, 63]);
+        for &(_, bucket) in tree.leaves().collect::<Vec<_>>().iter() {
+            assert!(bucket.len() <= 4);
+        }
+        assert_eq!(
+            tree.nearest(&[30.0], 2, &squared_euclidean).unwrap(),
+            vec![(4.0, &32), (36.0, &24)]
+        );
+
+        // a split undone entirely collapses back into one leaf
+        tree.retain(|&i| i < 20);
+        tree.compact();
+        assert!(tree.is_leaf());
+        assert_eq!(tree.points.as_ref().unwrap().len(), 3);
+        assert_eq!(tree.size(), 3);
+
+        // compacted trees keep splitting as before
+        for i in 100..120 {
+            tree.add([i as f64], i).unwrap();
+        }
+        assert_eq!(tree.size(), 23);
+        assert!(!tree.is_leaf());
+    }
+
     #[test]
     fn no_items_can_be_added_to_a_zero_capacity_kdtree() {
         let mut tree: KdTree<f64, i32, [f64; 2]> = KdTree::with_capacity(2, 0);
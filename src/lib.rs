@@ -53,15 +53,46 @@
 //! );
 //! ```
 
+#![cfg_attr(all(feature = "no_std", not(test)), no_std)]
+
+#[macro_use]
+extern crate alloc;
+#[cfg(not(all(feature = "no_std", not(test))))]
+extern crate core;
 extern crate num_traits;
+#[cfg(all(feature = "persist", feature = "no_std", not(test)))]
+extern crate std;
+
+#[cfg(any(feature = "persist", all(test, feature = "serialize")))]
+extern crate bincode;
+#[cfg(feature = "rayon")]
+extern crate rayon;
 
+#[cfg(feature = "serialize")]
+extern crate serde;
 #[cfg(feature = "serialize")]
 #[cfg_attr(feature = "serialize", macro_use)]
 extern crate serde_derive;
 
+pub mod arena;
 pub mod distance;
-mod heap_element;
+pub mod grid;
+pub mod heap_element;
 pub mod kdtree;
 mod util;
+pub use crate::arena::ArenaKdTree;
+pub use crate::grid::GridKdTree;
+pub use crate::heap_element::HeapElement;
+pub use crate::kdtree::nearest_between;
+#[cfg(feature = "rayon")]
+pub use crate::kdtree::nearest_between_par;
+pub use crate::kdtree::Bounds;
 pub use crate::kdtree::ErrorKind;
 pub use crate::kdtree::KdTree;
+pub use crate::kdtree::KdTreeBuilder;
+pub use crate::kdtree::KdTreeN;
+pub use crate::kdtree::Neighbor;
+pub use crate::kdtree::QueryScratch;
+pub use crate::kdtree::QueryStats;
+pub use crate::kdtree::SplitAxis;
+pub use crate::kdtree::SplitStrategy;
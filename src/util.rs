@@ -1,8 +1,37 @@
+use alloc::vec::Vec;
+
 use num_traits::Float;
 
-pub fn distance_to_space<F, T>(p1: &[T], min_bounds: &[T], max_bounds: &[T], distance: &F) -> T
+use crate::distance::haversine;
+use crate::kdtree::ErrorKind;
+
+pub fn check_point<T: Float>(dimensions: usize, point: &[T]) -> Result<(), ErrorKind> {
+    if dimensions != point.len() {
+        return Err(ErrorKind::WrongDimension);
+    }
+    for n in point {
+        if !n.is_finite() {
+            return Err(ErrorKind::NonFiniteCoordinate);
+        }
+    }
+    Ok(())
+}
+
+pub fn check_radius<T: Float>(radius: T) -> Result<(), ErrorKind> {
+    if radius.is_nan() || radius < T::zero() {
+        return Err(ErrorKind::InvalidRadius);
+    }
+    Ok(())
+}
+
+/// Returns `distance` from `p1` to the closest point of the box spanned by
+/// `min_bounds` and `max_bounds`, so its result is in the same units as the
+/// metric: squared for a squared metric. It bounds the distance to anything
+/// in the box from below for any metric that does not shrink as a coordinate
+/// moves away from `p1`, whatever type the metric returns.
+pub fn distance_to_space<F, T, D>(p1: &[T], min_bounds: &[T], max_bounds: &[T], distance: &F) -> D
 where
-    F: Fn(&[T], &[T]) -> T,
+    F: Fn(&[T], &[T]) -> D,
     T: Float,
 {
     let mut p2 = vec![T::nan(); p1.len()];
@@ -18,11 +47,175 @@ where
     distance(p1, &p2[..])
 }
 
+pub fn contained_in<T: Float>(p1: &[T], min_bounds: &[T], max_bounds: &[T]) -> bool {
+    p1.iter()
+        .zip(min_bounds.iter().zip(max_bounds.iter()))
+        .all(|(v, (l, h))| v >= l && v <= h)
+}
+
+/// Returns the median of `values`, moved up past the minimum if needed so
+/// that splitting on `< median` leaves neither side empty. `values` must
+/// contain at least two distinct, non-NaN values.
+pub fn split_median<T: Float>(values: &mut [T]) -> T {
+    values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = values[values.len() / 2];
+    if median > values[0] {
+        return median;
+    }
+    *values.iter().find(|&&v| v > median).unwrap()
+}
+
+/// Returns the great-circle distance from `p1` to the closest point of the
+/// `[latitude, longitude]` box spanned by `min_bounds` and `max_bounds`.
+pub fn haversine_to_space<T: Float>(p1: &[T], min_bounds: &[T], max_bounds: &[T]) -> T {
+    if min_bounds[0] > max_bounds[0] || min_bounds[1] > max_bounds[1] {
+        return T::infinity();
+    }
+    let (lat, lon) = (p1[0], p1[1]);
+    if lon >= min_bounds[1] && lon <= max_bounds[1] {
+        let closest = lat.max(min_bounds[0]).min(max_bounds[0]);
+        return haversine(p1, &[closest, lon]);
+    }
+    // outside the box's longitudes, the closest point lies on whichever edge
+    // meridian is nearer, and along a meridian the distance has one minimum
+    let full_turn = T::from(360.0).unwrap();
+    let to_min = modulo(min_bounds[1] - lon, full_turn);
+    let to_max = modulo(lon - max_bounds[1], full_turn);
+    let edge = if to_min < to_max {
+        min_bounds[1]
+    } else {
+        max_bounds[1]
+    };
+    let phi = lat.to_radians();
+    let closest = phi
+        .sin()
+        .atan2(phi.cos() * (edge - lon).to_radians().cos())
+        .to_degrees();
+    let mut best = haversine(p1, &[min_bounds[0], edge]).min(haversine(p1, &[max_bounds[0], edge]));
+    if closest > min_bounds[0] && closest < max_bounds[0] {
+        best = best.min(haversine(p1, &[closest, edge]));
+    }
+    best
+}
+
+/// Returns a lower bound on the cosine distance from `p1` to any point of the
+/// box spanned by `min_bounds` and `max_bounds`. The dot product
+/// is bounded per dimension, then divided by the smallest norm in the box when
+/// it is positive, or by the largest when it is negative.
+pub fn cosine_to_space<T: Float>(p1: &[T], min_bounds: &[T], max_bounds: &[T]) -> T {
+    let (mut dot, mut norm, mut nearest, mut farthest) =
+        (T::zero(), T::zero(), T::zero(), T::zero());
+    for ((&v, &l), &h) in p1.iter().zip(min_bounds.iter()).zip(max_bounds.iter()) {
+        if l > h {
+            return T::infinity();
+        }
+        dot = dot + (v * l).max(v * h);
+        norm = norm + v * v;
+        let closest = T::zero().max(l).min(h);
+        nearest = nearest + closest * closest;
+        farthest = farthest + (l * l).max(h * h);
+    }
+    if norm == T::zero() {
+        // every distance from a zero vector is one
+        return T::one();
+    }
+    let divisor = if dot > T::zero() { nearest } else { farthest };
+    if divisor == T::zero() {
+        // the box touches the origin, so it may hold vectors in any direction
+        return T::zero();
+    }
+    let similarity = dot / (norm.sqrt() * divisor.sqrt());
+    T::one() - similarity.min(T::one())
+}
+
+pub fn distance_to_space_periodic<F, T>(
+    p1: &[T],
+    min_bounds: &[T],
+    max_bounds: &[T],
+    period: &[T],
+    distance: &F,
+) -> T
+where
+    F: Fn(&[T], &[T]) -> T,
+    T: Float,
+{
+    let mut p2 = vec![T::nan(); p1.len()];
+    for i in 0..p1.len() {
+        let p = period[i];
+        if !is_periodic(p) {
+            if p1[i] > max_bounds[i] {
+                p2[i] = max_bounds[i];
+            } else if p1[i] < min_bounds[i] {
+                p2[i] = min_bounds[i];
+            } else {
+                p2[i] = p1[i];
+            }
+            continue;
+        }
+        let width = max_bounds[i] - min_bounds[i];
+        if width >= p || modulo(p1[i] - min_bounds[i], p) <= width {
+            p2[i] = p1[i];
+            continue;
+        }
+        // outside the bounds: walk up to the min bound or down to the max
+        // bound, whichever is shorter once wrapped
+        let up = modulo(min_bounds[i] - p1[i], p);
+        let down = modulo(p1[i] - max_bounds[i], p);
+        if up < down {
+            p2[i] = p1[i] + up;
+        } else {
+            p2[i] = p1[i] - down;
+        }
+    }
+    distance(p1, &p2[..])
+}
+
+/// Returns the copy of `p2`, shifted by whole periods, that lies closest to `p1`.
+pub fn periodic_image<T: Float>(p1: &[T], p2: &[T], period: &[T]) -> Vec<T> {
+    p1.iter()
+        .zip(p2.iter())
+        .zip(period.iter())
+        .map(|((&a, &b), &p)| {
+            if is_periodic(p) {
+                let diff = b - a;
+                a + diff - p * (diff / p).round()
+            } else {
+                b
+            }
+        })
+        .collect()
+}
+
+fn is_periodic<T: Float>(period: T) -> bool {
+    period.is_finite() && period > T::zero()
+}
+
+fn modulo<T: Float>(x: T, period: T) -> T {
+    let r = x % period;
+    if r < T::zero() {
+        r + period
+    } else {
+        r
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::distance_to_space;
+    use super::{
+        cosine_to_space, distance_to_space, distance_to_space_periodic, haversine_to_space,
+        periodic_image, split_median,
+    };
+    use crate::distance::cosine_distance;
+    use crate::distance::haversine;
     use crate::distance::squared_euclidean;
-    use std::f64::{INFINITY, NEG_INFINITY};
+
+    #[test]
+    fn test_split_median() {
+        assert_eq!(split_median(&mut [3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(split_median(&mut [4.0, 1.0, 3.0, 2.0]), 3.0);
+        assert_eq!(split_median(&mut [0.0, 0.0, 0.0, 5.0]), 5.0);
+        assert_eq!(split_median(&mut [1.0, 0.0, 1.0, 1.0, 2.0]), 1.0);
+    }
 
     #[test]
     fn test_normal_distance_to_space() {
@@ -35,7 +228,7 @@ mod tests {
         let dis = distance_to_space(
             &[0.0, 0.0],
             &[1.0, 1.0],
-            &[INFINITY, INFINITY],
+            &[f64::INFINITY, f64::INFINITY],
             &squared_euclidean,
         );
         assert_eq!(dis, 2.0);
@@ -45,8 +238,8 @@ mod tests {
     fn test_distance_inside_inf() {
         let dis = distance_to_space(
             &[2.0, 2.0],
-            &[NEG_INFINITY, NEG_INFINITY],
-            &[INFINITY, INFINITY],
+            &[f64::NEG_INFINITY, f64::NEG_INFINITY],
+            &[f64::INFINITY, f64::INFINITY],
             &squared_euclidean,
         );
         assert_eq!(dis, 0.0);
@@ -62,10 +255,99 @@ mod tests {
     fn distance_to_half_space() {
         let dis = distance_to_space(
             &[-2.0, 0.0],
-            &[0.0, NEG_INFINITY],
-            &[INFINITY, INFINITY],
+            &[0.0, f64::NEG_INFINITY],
+            &[f64::INFINITY, f64::INFINITY],
+            &squared_euclidean,
+        );
+        assert_eq!(dis, 4.0);
+    }
+
+    #[test]
+    fn test_periodic_distance_to_space() {
+        let period = [10.0, f64::INFINITY];
+        let dis = distance_to_space_periodic(
+            &[9.0, 0.0],
+            &[1.0, 0.0],
+            &[2.0, 0.0],
+            &period,
             &squared_euclidean,
         );
         assert_eq!(dis, 4.0);
+        let dis = distance_to_space_periodic(
+            &[5.0, 3.0],
+            &[1.0, 0.0],
+            &[2.0, 1.0],
+            &period,
+            &squared_euclidean,
+        );
+        assert_eq!(dis, 13.0);
+        let dis = distance_to_space_periodic(
+            &[21.5, 0.5],
+            &[1.0, 0.0],
+            &[2.0, 1.0],
+            &period,
+            &squared_euclidean,
+        );
+        assert_eq!(dis, 0.0);
+    }
+
+    #[test]
+    fn test_periodic_image() {
+        let period = [10.0, f64::INFINITY];
+        assert_eq!(
+            periodic_image(&[9.0, 0.0], &[1.0, 5.0], &period),
+            vec![11.0, 5.0]
+        );
+        assert_eq!(
+            periodic_image(&[1.0, 0.0], &[29.0, 5.0], &period),
+            vec![-1.0, 5.0]
+        );
+    }
+
+    #[test]
+    fn test_cosine_to_space() {
+        assert_eq!(cosine_to_space(&[1.0, 1.0], &[1.0, 1.0], &[2.0, 2.0]), 0.0);
+        assert_eq!(
+            cosine_to_space(&[1.0, 0.0], &[-1.0, -1.0], &[1.0, 1.0]),
+            0.0
+        );
+        assert_eq!(cosine_to_space(&[0.0, 0.0], &[1.0, 1.0], &[2.0, 2.0]), 1.0);
+        let min = [1.0, -2.0];
+        let max = [3.0, -1.0];
+        for &query in [[0.0, 1.0], [1.0, 0.0], [-1.0, 0.5], [2.0, -1.5]].iter() {
+            let mut closest = f64::INFINITY;
+            for i in 0..=100 {
+                for j in 0..=100 {
+                    let x = min[0] + (max[0] - min[0]) * i as f64 / 100.0;
+                    let y = min[1] + (max[1] - min[1]) * j as f64 / 100.0;
+                    closest = closest.min(cosine_distance(&query, &[x, y]));
+                }
+            }
+            assert!(cosine_to_space(&query, &min, &max) <= closest + 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_haversine_to_space() {
+        let min = [10.0, 20.0];
+        let max = [30.0, 40.0];
+        assert_eq!(haversine_to_space(&[20.0, 30.0], &min, &max), 0.0);
+        assert_eq!(
+            haversine_to_space(&[50.0, 30.0], &min, &max),
+            haversine(&[50.0, 30.0], &[30.0, 30.0])
+        );
+        for &query in [[60.0, 100.0], [-5.0, 0.0], [25.0, -170.0], [-80.0, 35.0]].iter() {
+            let mut closest = f64::INFINITY;
+            for i in 0..=200 {
+                for j in 0..=200 {
+                    let lat = min[0] + (max[0] - min[0]) * i as f64 / 200.0;
+                    let lon = min[1] + (max[1] - min[1]) * j as f64 / 200.0;
+                    closest = closest.min(haversine(&query, &[lat, lon]));
+                }
+            }
+            let bound = haversine_to_space(&query, &min, &max);
+            assert!(bound <= closest + 1e-6);
+            assert!(closest - bound < 1000.0);
+        }
     }
 }
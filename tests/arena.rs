@@ -0,0 +1,139 @@
+extern crate kdtree;
+extern crate rand;
+
+use kdtree::distance::squared_euclidean;
+use kdtree::ArenaKdTree;
+use kdtree::ErrorKind;
+use kdtree::KdTree;
+
+fn random_items(len: usize) -> Vec<([f64; 3], usize)> {
+    (0..len).map(|i| (rand::random(), i)).collect()
+}
+
+#[test]
+fn it_answers_like_a_built_kdtree() {
+    let items = random_items(2000);
+    let arena = ArenaKdTree::build_with_capacity(3, 8, items.clone()).unwrap();
+    let tree = KdTree::build_with_capacity(3, 8, items).unwrap();
+    assert_eq!(arena.size(), 2000);
+    assert_eq!(arena.dimensions(), 3);
+
+    for _ in 0..50 {
+        let query: [f64; 3] = rand::random();
+        assert_eq!(
+            arena.nearest(&query, 10, &squared_euclidean).unwrap(),
+            tree.nearest(&query, 10, &squared_euclidean).unwrap()
+        );
+        assert_eq!(
+            arena.nearest_one(&query, &squared_euclidean).unwrap(),
+            tree.nearest_one(&query, &squared_euclidean).unwrap()
+        );
+        assert_eq!(
+            arena.within(&query, 0.02, &squared_euclidean).unwrap(),
+            tree.within(&query, 0.02, &squared_euclidean).unwrap()
+        );
+        assert_eq!(
+            arena
+                .nearest_within(&query, 10, 0.01, &squared_euclidean)
+                .unwrap(),
+            tree.nearest_within(&query, 10, 0.01, &squared_euclidean)
+                .unwrap()
+        );
+        assert_eq!(
+            arena
+                .count_within(&query, 0.02, &squared_euclidean)
+                .unwrap(),
+            tree.count_within(&query, 0.02, &squared_euclidean).unwrap()
+        );
+        let lazy: Vec<_> = arena
+            .iter_nearest(&query, &squared_euclidean)
+            .unwrap()
+            .take(20)
+            .collect();
+        assert_eq!(lazy, tree.nearest(&query, 20, &squared_euclidean).unwrap());
+
+        let min = [query[0] - 0.1, query[1] - 0.2, query[2] - 0.3];
+        let max = [query[0] + 0.1, query[1] + 0.2, query[2] + 0.3];
+        let mut boxed = arena.within_bbox(&min, &max).unwrap();
+        let mut expected = tree.within_bbox(&min, &max).unwrap();
+        boxed.sort();
+        expected.sort();
+        assert_eq!(boxed, expected);
+    }
+
+    assert_eq!(
+        arena
+            .iter_nearest(&[0.5, 0.5, 0.5], &squared_euclidean)
+            .unwrap()
+            .count(),
+        2000
+    );
+    let mut listed: Vec<_> = arena.iter().map(|(p, &i)| (p.to_vec(), i)).collect();
+    let mut stored: Vec<_> = tree.iter().map(|(p, &i)| (p.to_vec(), i)).collect();
+    listed.sort_by_key(|x| x.1);
+    stored.sort_by_key(|x| x.1);
+    assert_eq!(listed, stored);
+}
+
+#[test]
+fn it_handles_repeated_points() {
+    let items: Vec<_> = (0..100).map(|i| ([(i % 3) as f64, 0.0], i)).collect();
+    let arena = ArenaKdTree::build_with_capacity(2, 4, items).unwrap();
+    let found = arena.within(&[1.0, 0.0], 0.0, &squared_euclidean).unwrap();
+    assert_eq!(found.len(), 33);
+    assert!(found.iter().all(|&(d, &i)| d == 0.0 && i % 3 == 1));
+}
+
+#[test]
+fn it_handles_errors() {
+    let empty: ArenaKdTree<f64, usize> = ArenaKdTree::build::<[f64; 2]>(2, vec![]).unwrap();
+    assert!(empty.is_empty());
+    assert_eq!(
+        empty.nearest(&[0.0, 0.0], 3, &squared_euclidean),
+        Ok(vec![])
+    );
+    assert_eq!(empty.nearest_one(&[0.0, 0.0], &squared_euclidean), Ok(None));
+    assert_eq!(
+        empty.count_within(&[0.0, 0.0], 1.0, &squared_euclidean),
+        Ok(0)
+    );
+    assert_eq!(empty.within_bbox(&[0.0, 0.0], &[1.0, 1.0]), Ok(vec![]));
+    assert_eq!(
+        empty
+            .iter_nearest(&[0.0, 0.0], &squared_euclidean)
+            .unwrap()
+            .next(),
+        None
+    );
+    assert_eq!(empty.iter().count(), 0);
+
+    assert_eq!(
+        ArenaKdTree::build(2, vec![([0.0, f64::NAN], 0)]).err(),
+        Some(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        ArenaKdTree::build_with_capacity(2, 0, vec![([0.0, 0.0], 0)]).err(),
+        Some(ErrorKind::ZeroCapacity)
+    );
+    assert_eq!(
+        ArenaKdTree::<f64, _>::build(0, vec![([], 0)]).err(),
+        Some(ErrorKind::WrongDimension)
+    );
+    let arena = ArenaKdTree::build(2, vec![([0.0, 0.0], 0)]).unwrap();
+    assert_eq!(
+        arena.nearest(&[0.0], 1, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+    assert_eq!(
+        arena.within(&[0.0, 0.0], -1.0, &squared_euclidean),
+        Err(ErrorKind::InvalidRadius)
+    );
+    assert_eq!(
+        arena.nearest_within(&[0.0, 0.0], 1, f64::NAN, &squared_euclidean),
+        Err(ErrorKind::InvalidRadius)
+    );
+    assert_eq!(
+        arena.within_bbox(&[1.0, 0.0], &[0.0, 1.0]),
+        Err(ErrorKind::InvalidBounds)
+    );
+}
@@ -56,6 +56,23 @@ fn it_works() {
     kdtree.within(&POINT_B.0, 2.0, &new_dist).unwrap();
     assert_eq!(6, count.swap(0, Ordering::SeqCst));
 
+    kdtree
+        .nearest_within(&POINT_A.0, 4, 0.0, &new_dist)
+        .unwrap();
+    assert_eq!(2, count.swap(0, Ordering::SeqCst));
+
+    // the k limit stops the search as early as plain `nearest`
+    kdtree
+        .nearest_within(&POINT_A.0, 1, 100.0, &new_dist)
+        .unwrap();
+    assert_eq!(2, count.swap(0, Ordering::SeqCst));
+
+    // and the radius as early as `within`
+    kdtree
+        .nearest_within(&POINT_B.0, 4, 1.0, &new_dist)
+        .unwrap();
+    assert_eq!(3, count.swap(0, Ordering::SeqCst));
+
     let mut iter = kdtree.iter_nearest(&POINT_A.0, &new_dist).unwrap();
     assert_eq!(0, count.swap(0, Ordering::SeqCst));
 
@@ -71,3 +88,83 @@ fn it_works() {
     iter.next().unwrap();
     assert_eq!(0, count.swap(0, Ordering::SeqCst));
 }
+
+#[test]
+fn it_reports_query_stats() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..8 {
+        for y in 0..8 {
+            kdtree.add([x as f64, y as f64], x * 8 + y).unwrap();
+        }
+    }
+
+    let count = AtomicUsize::new(0);
+    let new_dist = |a: &[f64], b: &[f64]| {
+        count.fetch_add(1, Ordering::SeqCst);
+        squared_euclidean(a, b)
+    };
+
+    for &(query, num) in [([3.2, 4.1], 1), ([0.0, 0.0], 5), ([7.5, 2.0], 64)].iter() {
+        let (nearest, stats) = kdtree.nearest_with_stats(&query, num, &new_dist).unwrap();
+        // the remaining calls measure the distance to node bounds
+        assert!(stats.points_evaluated >= num);
+        assert!(stats.points_evaluated < count.swap(0, Ordering::SeqCst));
+        assert_eq!(
+            nearest,
+            kdtree.nearest(&query, num, &squared_euclidean).unwrap()
+        );
+        assert!(stats.leaves_visited >= 1);
+        assert!(stats.nodes_visited > stats.leaves_visited);
+        assert!(stats.nodes_visited <= kdtree.node_count());
+        assert!(stats.leaves_visited <= kdtree.leaf_count());
+    }
+
+    let (_, everything) = kdtree
+        .nearest_with_stats(&[0.0, 0.0], 64, &squared_euclidean)
+        .unwrap();
+    assert_eq!(everything.points_evaluated, 64);
+    assert_eq!(everything.leaves_visited, kdtree.leaf_count());
+
+    let (nearest, stats) = kdtree
+        .nearest_with_stats(&[0.0, 0.0], 0, &new_dist)
+        .unwrap();
+    assert!(nearest.is_empty());
+    assert_eq!(stats, Default::default());
+}
+
+#[test]
+fn it_stops_at_its_node_budget() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..8 {
+        for y in 0..8 {
+            kdtree.add([x as f64, y as f64], x * 8 + y).unwrap();
+        }
+    }
+
+    let query = [3.2, 4.1];
+    let (_, stats) = kdtree
+        .nearest_with_stats(&query, 5, &squared_euclidean)
+        .unwrap();
+    let (found, finished) = kdtree
+        .nearest_budget(&query, 5, stats.nodes_visited, &squared_euclidean)
+        .unwrap();
+    assert!(finished);
+    assert_eq!(
+        found,
+        kdtree.nearest(&query, 5, &squared_euclidean).unwrap()
+    );
+
+    // one descent reaches a leaf, which holds the query's own cell
+    let (found, finished) = kdtree
+        .nearest_budget(&query, 5, 1, &squared_euclidean)
+        .unwrap();
+    assert!(!finished);
+    assert!(!found.is_empty() && found.len() < 5);
+    assert_eq!(found[0].1, &28);
+
+    let (found, finished) = kdtree
+        .nearest_budget(&query, 5, 0, &squared_euclidean)
+        .unwrap();
+    assert!(!finished);
+    assert!(found.is_empty());
+}
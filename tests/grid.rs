@@ -0,0 +1,110 @@
+extern crate kdtree;
+
+use kdtree::ErrorKind;
+use kdtree::GridKdTree;
+
+#[test]
+fn it_indexes_a_grid() {
+    let mut grid = GridKdTree::with_capacity(4);
+    assert!(grid.is_empty());
+    for x in -10..10 {
+        for y in -10..10 {
+            grid.add([x, y], (x, y)).unwrap();
+        }
+    }
+    assert_eq!(grid.size(), 400);
+
+    assert_eq!(grid.nearest_one(&[3, -4]), Some((0, &(3, -4))));
+    let nearest = grid.nearest(&[20, 0], 3);
+    assert_eq!(nearest[0], (121, &(9, 0)));
+    assert_eq!(nearest[1].0, 122);
+    assert_eq!(nearest[2].0, 122);
+
+    let mut within = grid.within(&[0, 0], 1);
+    within.sort();
+    assert_eq!(
+        within,
+        vec![
+            (0, &(0, 0)),
+            (1, &(-1, 0)),
+            (1, &(0, -1)),
+            (1, &(0, 1)),
+            (1, &(1, 0))
+        ]
+    );
+    assert_eq!(grid.within(&[0, 0], 2).len(), 9);
+    assert!(grid.within(&[100, 100], 100).is_empty());
+}
+
+#[test]
+fn it_matches_a_linear_scan() {
+    // a fixed sequence of scattered points in a 2000 by 2000 square
+    let mut seed = 1u64;
+    let mut rand = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        ((seed >> 33) % 2000) as i64 - 1000
+    };
+    let points: Vec<[i64; 2]> = (0..500).map(|_| [rand(), rand()]).collect();
+    let mut grid = GridKdTree::with_capacity(8);
+    for (i, &point) in points.iter().enumerate() {
+        grid.add(point, i).unwrap();
+    }
+
+    let squared = |a: &[i64; 2], b: &[i64; 2]| {
+        let (dx, dy) = (a[0] - b[0], a[1] - b[1]);
+        (dx * dx + dy * dy) as u128
+    };
+    for query in [[0, 0], [-1000, 999], [5000, -5000], [17, -423]].iter() {
+        let mut expected: Vec<_> = points.iter().map(|p| squared(query, p)).collect();
+        expected.sort();
+        let found: Vec<_> = grid
+            .nearest(query, 10)
+            .into_iter()
+            .map(|(d, _)| d)
+            .collect();
+        assert_eq!(found, expected[..10].to_vec());
+        for &(distance, &i) in grid.nearest(query, 10).iter() {
+            assert_eq!(distance, squared(query, &points[i]));
+        }
+
+        let radius = 40_000;
+        let within = grid.within(query, radius);
+        assert_eq!(
+            within.len(),
+            expected.iter().filter(|&&d| d <= radius).count()
+        );
+        assert!(within.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+}
+
+#[test]
+fn it_keeps_large_distances_exact() {
+    let mut grid = GridKdTree::new();
+    grid.add([i64::MIN, i64::MIN], 0).unwrap();
+    grid.add([i64::MAX, 5], 1).unwrap();
+    grid.add([100_000_000_000, 1], 2).unwrap();
+
+    // 10^22 + 1 is not exactly representable as an `f64`
+    assert_eq!(
+        grid.nearest_one(&[0, 0]),
+        Some((10_000_000_000_000_000_000_001, &2))
+    );
+    let far = grid.nearest(&[i64::MAX, i64::MAX], 3);
+    assert_eq!(far[2], (u128::MAX, &0));
+
+    let mut line = GridKdTree::with_capacity(1);
+    line.add([i64::MIN], 0).unwrap();
+    line.add([i64::MAX], 1).unwrap();
+    line.add([-1], 2).unwrap();
+    let span = u128::from(u64::MAX);
+    assert_eq!(
+        line.nearest(&[i64::MAX], 3),
+        vec![(0, &1), (1 << 126, &2), (span * span, &0)]
+    );
+    assert_eq!(line.within(&[i64::MIN], 0), vec![(0, &0)]);
+
+    assert_eq!(
+        GridKdTree::<usize, 2>::with_capacity(0).add([0, 0], 0),
+        Err(ErrorKind::ZeroCapacity)
+    );
+}
@@ -1,8 +1,20 @@
 extern crate kdtree;
 
-use kdtree::distance::squared_euclidean;
+use std::collections::BinaryHeap;
+
+use kdtree::distance::{
+    chebyshev, cosine_distance, distance_to_space, haversine, manhattan, minkowski,
+    squared_euclidean, weighted_squared_euclidean, Chebyshev, Euclidean, Manhattan, Metric,
+    SquaredEuclidean,
+};
+use kdtree::nearest_between;
 use kdtree::ErrorKind;
+use kdtree::HeapElement;
 use kdtree::KdTree;
+use kdtree::KdTreeBuilder;
+use kdtree::KdTreeN;
+use kdtree::QueryScratch;
+use kdtree::SplitStrategy;
 
 static POINT_A: ([f64; 2], usize) = ([0f64, 0f64], 0);
 static POINT_B: ([f64; 2], usize) = ([1f64, 1f64], 1);
@@ -50,6 +62,15 @@ fn it_works() {
         vec![(0f64, &1), (2f64, &0), (2f64, &2), (8f64, &3)]
     );
 
+    assert_eq!(
+        kdtree.nearest_one(&POINT_A.0, &squared_euclidean).unwrap(),
+        Some((0f64, &0))
+    );
+    assert_eq!(
+        kdtree.nearest_one(&[2.5, 2.0], &squared_euclidean).unwrap(),
+        Some((0.25, &2))
+    );
+
     assert_eq!(
         kdtree.within(&POINT_A.0, 0.0, &squared_euclidean).unwrap(),
         vec![(0.0, &0)]
@@ -130,71 +151,2580 @@ fn it_works_with_vec() {
 }
 
 #[test]
-fn handles_zero_capacity() {
-    let mut kdtree = KdTree::with_capacity(2, 0);
+fn it_works_when_built_in_bulk() {
+    let mut items = vec![];
+    for x in 0..20 {
+        for y in 0..20 {
+            items.push(([x as f64, y as f64], x * 20 + y));
+        }
+    }
+    let mut added = KdTree::with_capacity(2, 4);
+    for item in items.iter() {
+        added.add(item.0, item.1).unwrap();
+    }
+    let built = KdTree::build_with_capacity(2, 4, items).unwrap();
+
+    assert_eq!(built.size(), 400);
+    let distances = |found: Vec<(f64, &usize)>| found.into_iter().map(|x| x.0).collect::<Vec<_>>();
+    for query in [[0.0, 0.0], [7.2, 3.9], [19.0, 10.5], [-4.0, 25.0]].iter() {
+        assert_eq!(
+            distances(built.nearest(query, 5, &squared_euclidean).unwrap()),
+            distances(added.nearest(query, 5, &squared_euclidean).unwrap())
+        );
+        assert_eq!(
+            built.within(query, 4.0, &squared_euclidean).unwrap().len(),
+            added.within(query, 4.0, &squared_euclidean).unwrap().len()
+        );
+    }
 
     assert_eq!(
-        kdtree.add(&POINT_A.0, POINT_A.1),
-        Err(ErrorKind::ZeroCapacity)
+        KdTree::<f64, usize, [f64; 1]>::build(2, vec![([0f64], 0)]).unwrap_err(),
+        ErrorKind::WrongDimension
     );
     assert_eq!(
-        kdtree.nearest(&POINT_A.0, 1, &squared_euclidean).unwrap(),
-        vec![]
+        KdTree::build_with_capacity(2, 0, vec![(POINT_A.0, POINT_A.1)]).unwrap_err(),
+        ErrorKind::ZeroCapacity
+    );
+    assert_eq!(
+        KdTree::<f64, usize, [f64; 2]>::build(2, vec![])
+            .unwrap()
+            .size(),
+        0
     );
 }
 
 #[test]
-fn handles_wrong_dimension() {
-    let point = ([0f64], 0f64);
-    let mut kdtree = KdTree::with_capacity(2, 1);
+#[cfg(not(feature = "no_std"))]
+fn it_can_be_exported_to_csv() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for i in 0..20 {
+        kdtree.add([i as f64 * 0.1, -(i as f64) / 3.0], i).unwrap();
+    }
+    let mut csv = vec![];
+    kdtree.to_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count(), 20);
+    assert!(csv.lines().any(|line| line == "0.1,-0.3333333333333333"));
 
+    let with_blanks = format!("\n{}\n  \n", csv);
+    let loaded: KdTree<f64, (), Vec<f64>> = KdTree::from_csv(2, with_blanks.as_bytes()).unwrap();
+    assert_eq!(loaded.size(), 20);
+    let mut points: Vec<_> = loaded.iter().map(|(p, _)| p.clone()).collect();
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut expected: Vec<_> = kdtree.iter().map(|(p, _)| p.to_vec()).collect();
+    expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(points, expected);
+
+    // loaded points carry no data to tell them apart by
+    let loaded: KdTree<f64, (), Vec<f64>> =
+        KdTree::from_csv(2, &b"0,0\n1,0\n5,5\n5,6\n"[..]).unwrap();
     assert_eq!(
-        kdtree.add(&point.0, point.1),
-        Err(ErrorKind::WrongDimension)
+        loaded.closest_pair(&squared_euclidean),
+        Some((1.0, &(), &()))
     );
+    let distances = loaded.k_distances(1, &squared_euclidean).unwrap();
+    assert_eq!(distances, vec![(&(), 1.0); 4]);
+
+    let error = |csv: &str| {
+        let err = KdTree::<f64, (), Vec<f64>>::from_csv(2, csv.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        err.to_string()
+    };
+    assert_eq!(error("1,2\n3\n"), "line 2: expected 2 columns, found 1");
+    assert!(error("1,2\n\n1,x\n").starts_with("line 3: cannot parse"));
+    assert!(error("inf, 1\n").starts_with("line 1: "));
     assert_eq!(
-        kdtree.nearest(&point.0, 1, &squared_euclidean),
+        KdTree::<f64, (), Vec<f64>>::from_csv(2, &b""[..])
+            .unwrap()
+            .size(),
+        0
+    );
+}
+
+#[test]
+#[cfg(feature = "persist")]
+fn it_can_be_saved_and_loaded() {
+    let mut kdtree: KdTree<f64, usize, Vec<f64>> = KdTree::with_capacity(2, 2);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree
+                .add(vec![x as f64, y as f64 * 0.5], x * 10 + y)
+                .unwrap();
+        }
+    }
+    let path = std::env::temp_dir().join(format!("kdtree-{}.bin", std::process::id()));
+    kdtree.save_to_path(&path).unwrap();
+    let loaded: KdTree<f64, usize, Vec<f64>> = KdTree::load_from_path(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded.size(), kdtree.size());
+    for query in [[4.2, 1.1], [-1.0, 8.0], [9.0, 4.5]].iter() {
+        assert_eq!(
+            loaded.nearest(query, 5, &squared_euclidean).unwrap(),
+            kdtree.nearest(query, 5, &squared_euclidean).unwrap()
+        );
+    }
+
+    assert_eq!(
+        KdTree::<f64, usize, Vec<f64>>::load_from_path(&path)
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::NotFound
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn it_builds_in_parallel() {
+    let items: Vec<_> = (0..20000)
+        .map(|i| {
+            let point = [
+                ((i * 7919) % 1013) as f64,
+                ((i * 104729) % 997) as f64,
+                (i % 10) as f64,
+            ];
+            (point, i)
+        })
+        .collect();
+    let serial = KdTree::build_with_capacity(3, 8, items.clone()).unwrap();
+    let parallel = KdTree::build_par_with_capacity(3, 8, items.clone()).unwrap();
+    assert_eq!(parallel.to_dot(), serial.to_dot());
+    assert!(parallel.iter().eq(serial.iter()));
+    assert_eq!(KdTree::build_par(3, items).unwrap().size(), 20000);
+
+    assert_eq!(
+        KdTree::build_par(2, vec![([0.0, f64::NAN], 0)]).err(),
+        Some(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn it_works_in_batches() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    kdtree.add(&POINT_D.0, POINT_D.1).unwrap();
+
+    let queries: Vec<&[f64]> = vec![&POINT_A.0, &POINT_D.0, &[2.5, 2.0]];
+    assert_eq!(
+        kdtree
+            .nearest_batch(&queries, 2, &squared_euclidean)
+            .unwrap(),
+        vec![
+            vec![(0f64, &0), (2f64, &1)],
+            vec![(0f64, &3), (2f64, &2)],
+            vec![(0.25, &2), (1.25, &3)],
+        ]
+    );
+    let queries: Vec<&[f64]> = vec![&POINT_A.0, &[0f64]];
+    assert_eq!(
+        kdtree.nearest_batch(&queries, 2, &squared_euclidean),
         Err(ErrorKind::WrongDimension)
     );
 }
 
 #[test]
-fn handles_non_finite_coordinate() {
-    let point_a = ([std::f64::NAN, std::f64::NAN], 0f64);
-    let point_b = ([std::f64::INFINITY, std::f64::INFINITY], 0f64);
-    let mut kdtree = KdTree::with_capacity(2, 1);
+fn it_iterates_in_nearest_order() {
+    let mut kdtree = KdTree::with_capacity(2, 3);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64 * 1.5], x * 10 + y).unwrap();
+        }
+    }
+    for &query in [[4.2, 7.1], [-3.0, 0.5], [9.9, 20.0]].iter() {
+        let all: Vec<_> = kdtree
+            .iter_nearest(&query, &squared_euclidean)
+            .unwrap()
+            .collect();
+        assert_eq!(all.len(), 100);
+        assert!(all.windows(2).all(|w| w[0].0 <= w[1].0));
+        for &k in [1, 7, 100].iter() {
+            let distances = |found: &[(f64, &usize)]| found.iter().map(|x| x.0).collect::<Vec<_>>();
+            assert_eq!(
+                distances(&all[..k]),
+                distances(&kdtree.nearest(&query, k, &squared_euclidean).unwrap())
+            );
+        }
+    }
+}
+
+#[test]
+fn it_pages_through_nearest_points() {
+    let mut kdtree = KdTree::with_capacity(2, 3);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64 * 1.5], x * 10 + y).unwrap();
+        }
+    }
+    let query = [4.2, 7.1];
+    let all: Vec<_> = kdtree
+        .iter_nearest(&query, &squared_euclidean)
+        .unwrap()
+        .collect();
+    let mut cursor = kdtree.nearest_cursor(&query, &squared_euclidean).unwrap();
+    let mut paged = Vec::new();
+    for _ in 0..5 {
+        let batch = cursor.next_batch(20);
+        assert_eq!(batch.len(), 20);
+        paged.extend(batch);
+    }
+    assert_eq!(paged, all);
+    assert!(cursor.next_batch(20).is_empty());
 
+    let mut cursor = kdtree.nearest_cursor(&query, &squared_euclidean).unwrap();
+    assert!(cursor.next_batch(0).is_empty());
     assert_eq!(
-        kdtree.add(&point_a.0, point_a.1),
-        Err(ErrorKind::NonFiniteCoordinate)
+        cursor.next_batch(7),
+        kdtree.nearest(&query, 7, &squared_euclidean).unwrap()
     );
+    assert_eq!(cursor.next_batch(200).len(), 93);
+    assert!(kdtree.nearest_cursor(&[0.0], &squared_euclidean).is_err());
+}
+
+#[test]
+fn it_orders_by_a_custom_key() {
+    // grid distance first, then the largest single step
+    let key = |a: &[f64], b: &[f64]| {
+        let steps = a.iter().zip(b.iter()).map(|(x, y)| (x - y).abs() as u32);
+        let longest = steps.clone().max().unwrap();
+        (steps.sum::<u32>(), longest)
+    };
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    let mut points = Vec::new();
+    for i in 0..40 {
+        let point = [((i * 7) % 13) as f64, ((i * 5) % 11) as f64];
+        kdtree.add(point, i).unwrap();
+        points.push((point, i));
+    }
+    assert!(kdtree
+        .nearest_by_key(&[4.0, 5.0], 0, &key)
+        .unwrap()
+        .is_empty());
+    for &query in [[4.0, 5.0], [-3.0, 20.0], [12.0, 0.0]].iter() {
+        let mut expected: Vec<_> = points.iter().map(|p| key(&query, &p.0)).collect();
+        expected.sort();
+        let found = kdtree.nearest_by_key(&query, 9, &key).unwrap();
+        let keys: Vec<_> = found.iter().map(|x| x.0).collect();
+        assert_eq!(keys, &expected[..9]);
+        for &(k, &i) in found.iter() {
+            assert_eq!(k, key(&query, &points[i].0));
+        }
+    }
     assert_eq!(
-        kdtree.add(&point_b.0, point_b.1),
-        Err(ErrorKind::NonFiniteCoordinate)
+        kdtree.nearest_by_key(&[0.0, 0.0], 100, &key).unwrap().len(),
+        40
     );
     assert_eq!(
-        kdtree.nearest(&point_a.0, 1, &squared_euclidean),
-        Err(ErrorKind::NonFiniteCoordinate)
+        kdtree.nearest_by_key(&[0.0], 1, &key),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_finds_the_closest_pair() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    assert_eq!(kdtree.closest_pair(&squared_euclidean), None);
+    kdtree.add([0.0, 0.0], 0).unwrap();
+    assert_eq!(kdtree.closest_pair(&squared_euclidean), None);
+    for i in 1..30 {
+        let i = i as f64;
+        kdtree.add([i * 1.5, (i * 7.0) % 5.0], i as usize).unwrap();
+    }
+    kdtree.add([19.7, 1.1], 30).unwrap();
+
+    let (dist, a, b) = kdtree.closest_pair(&squared_euclidean).unwrap();
+    let mut pair = [*a, *b];
+    pair.sort();
+    assert_eq!(pair, [13, 30]);
+    assert!((dist - 0.05).abs() < 1e-9);
+
+    kdtree.add([3.0, 4.0], 31).unwrap();
+    let (dist, a, b) = kdtree.closest_pair(&squared_euclidean).unwrap();
+    let mut pair = [*a, *b];
+    pair.sort();
+    assert_eq!((dist, pair), (0.0, [2, 31]));
+
+    let mut unit: KdTree<f64, (), [f64; 2]> = KdTree::with_capacity(2, 1);
+    for &point in [[0.0, 0.0], [3.0, 0.0], [5.0, 5.0], [5.0, 7.0]].iter() {
+        unit.add(point, ()).unwrap();
+    }
+    assert_eq!(unit.closest_pair(&squared_euclidean), Some((4.0, &(), &())));
+    unit.add([5.0, 5.0], ()).unwrap();
+    assert_eq!(unit.closest_pair(&squared_euclidean), Some((0.0, &(), &())));
+}
+
+#[test]
+fn it_finds_the_nearest_between_two_trees() {
+    let mut sensors = KdTree::with_capacity(2, 2);
+    for i in 0..20 {
+        sensors.add([i as f64, (i % 3) as f64], i).unwrap();
+    }
+    let mut landmarks = KdTree::with_capacity(2, 2);
+    for &(point, name) in [
+        ([0.0, 0.0], "origin"),
+        ([10.0, 1.0], "middle"),
+        ([19.0, 5.0], "end"),
+    ]
+    .iter()
+    {
+        landmarks.add(point, name).unwrap();
+    }
+
+    let pairs = nearest_between(&sensors, &landmarks, &squared_euclidean).unwrap();
+    assert_eq!(pairs.len(), 20);
+    for ((point, sensor), &(paired, dist, landmark)) in sensors.iter().zip(pairs.iter()) {
+        assert_eq!(sensor, paired);
+        assert_eq!(
+            Some((dist, landmark)),
+            landmarks.nearest_one(point, &squared_euclidean).unwrap()
+        );
+    }
+    assert_eq!(pairs.iter().find(|p| *p.0 == 0).unwrap().2, &"origin");
+    assert_eq!(pairs.iter().find(|p| *p.0 == 10).unwrap().2, &"middle");
+    #[cfg(feature = "rayon")]
+    assert_eq!(
+        kdtree::nearest_between_par(&sensors, &landmarks, &squared_euclidean).unwrap(),
+        pairs
     );
+
+    let empty: KdTree<f64, (), [f64; 2]> = KdTree::new(2);
+    assert!(nearest_between(&sensors, &empty, &squared_euclidean)
+        .unwrap()
+        .is_empty());
+    let other: KdTree<f64, (), [f64; 1]> = KdTree::new(1);
     assert_eq!(
-        kdtree.nearest(&point_b.0, 1, &squared_euclidean),
-        Err(ErrorKind::NonFiniteCoordinate)
+        nearest_between(&sensors, &other, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
     );
 }
 
 #[test]
-fn handles_singularity() {
-    let mut kdtree = KdTree::with_capacity(2, 1);
-    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
-    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+fn it_can_be_queried_from_many_threads() {
+    let mut kdtree = KdTree::with_capacity(2, 4);
+    for i in 0..100 {
+        kdtree.add([i as f64, (i % 7) as f64], i).unwrap();
+    }
+    let kdtree = std::sync::Arc::new(kdtree);
+    let queries = [[0.0, 3.0], [25.0, 3.0], [50.5, 6.0], [99.0, 0.0]];
+    let handles: Vec<_> = queries
+        .iter()
+        .map(|&query| {
+            let kdtree = kdtree.clone();
+            std::thread::spawn(move || {
+                let nearest = kdtree.nearest(&query, 3, &squared_euclidean).unwrap();
+                nearest
+                    .into_iter()
+                    .map(|(d, &i)| (d, i))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for (query, handle) in queries.iter().zip(handles) {
+        let nearest = kdtree.nearest(query, 3, &squared_euclidean).unwrap();
+        let expected: Vec<_> = nearest.into_iter().map(|(d, &i)| (d, i)).collect();
+        assert_eq!(handle.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn it_works_with_reused_buffers() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
     kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
     kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
-    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
-    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
-    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
     kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
-    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
-    assert_eq!(kdtree.size(), 9);
+    kdtree.add(&POINT_D.0, POINT_D.1).unwrap();
+
+    let mut out = vec![];
+    let mut scratch = QueryScratch::new();
+    for &(query, num) in [(&POINT_A.0, 3), (&POINT_B.0, 4), (&POINT_D.0, 1)].iter() {
+        kdtree
+            .nearest_into(query, num, &squared_euclidean, &mut out, &mut scratch)
+            .unwrap();
+        assert_eq!(out, kdtree.nearest(query, num, &squared_euclidean).unwrap());
+    }
+    kdtree
+        .nearest_into(&POINT_A.0, 0, &squared_euclidean, &mut out, &mut scratch)
+        .unwrap();
+    assert_eq!(out, vec![]);
+
+    for &(query, radius) in [(&POINT_B.0, 2.0), (&POINT_A.0, 8.0), (&POINT_D.0, 100.0)].iter() {
+        kdtree
+            .within_into(query, radius, &squared_euclidean, &mut out, &mut scratch)
+            .unwrap();
+        assert_eq!(
+            out,
+            kdtree.within(query, radius, &squared_euclidean).unwrap()
+        );
+    }
+    kdtree
+        .within_into(
+            &[10f64, 10f64],
+            1.0,
+            &squared_euclidean,
+            &mut out,
+            &mut scratch,
+        )
+        .unwrap();
+    assert_eq!(out, vec![]);
+    assert_eq!(
+        kdtree.within_into(&POINT_A.0, -1.0, &squared_euclidean, &mut out, &mut scratch),
+        Err(ErrorKind::InvalidRadius)
+    );
+    assert_eq!(
+        kdtree.nearest_into(&[0f64], 1, &squared_euclidean, &mut out, &mut scratch),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_works_with_fixed_dimensions() {
+    let items = vec![POINT_A, POINT_B, POINT_C, POINT_D];
+    let boxed = KdTree::build_with_capacity(2, 2, items.clone()).unwrap();
+    let mut fixed: KdTreeN<f64, usize, [f64; 2], 2> = KdTreeN::build_fixed(items).unwrap();
+    assert_eq!(fixed.size(), 4);
+    for query in [[0.0, 0.0], [1.4, 2.2], [3.0, 3.0]].iter() {
+        assert_eq!(
+            fixed.nearest(query, 3, &squared_euclidean).unwrap(),
+            boxed.nearest(query, 3, &squared_euclidean).unwrap()
+        );
+    }
+    assert!(fixed.memory_usage() < boxed.memory_usage());
+
+    fixed.add([4.0, 4.0], 4).unwrap();
+    assert_eq!(
+        fixed.nearest(&[5.0, 5.0], 1, &squared_euclidean).unwrap(),
+        vec![(2f64, &4)]
+    );
+    assert_eq!(
+        fixed.add([f64::NAN, 0.0], 5),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+
+    let empty: KdTreeN<f64, usize, [f64; 3], 3> = KdTreeN::fixed();
+    assert_eq!(empty.size(), 0);
+}
+
+#[test]
+fn it_classifies_by_majority_vote() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for &(point, label) in [
+        ([0.0, 0.0], "red"),
+        ([1.0, 0.0], "red"),
+        ([0.0, 1.0], "red"),
+        ([5.0, 5.0], "blue"),
+        ([6.0, 5.0], "blue"),
+        ([2.0, 2.0], "green"),
+    ]
+    .iter()
+    {
+        kdtree.add(point, label).unwrap();
+    }
+    let classify = |point: [f64; 2], k| kdtree.classify(&point, k, &squared_euclidean).unwrap();
+    assert_eq!(classify([0.2, 0.2], 3), Some(&"red"));
+    assert_eq!(classify([5.5, 4.0], 3), Some(&"blue"));
+    // the one green point is outvoted by the red ones behind it
+    assert_eq!(classify([2.0, 1.9], 1), Some(&"green"));
+    assert_eq!(classify([2.0, 1.9], 4), Some(&"red"));
+    // one vote each for green and blue, and green is nearer
+    assert_eq!(classify([3.0, 3.0], 2), Some(&"green"));
+    assert_eq!(classify([0.0, 0.0], 0), None);
+
+    let empty: KdTree<f64, &str, [f64; 2]> = KdTree::new(2);
+    assert_eq!(empty.classify(&[0.0, 0.0], 3, &squared_euclidean), Ok(None));
+}
+
+#[test]
+fn it_interpolates_by_inverse_distance() {
+    let mut kdtree = KdTree::with_capacity(1, 2);
+    for &(x, value) in [(0.0, 10.0), (1.0, 20.0), (3.0, 40.0), (10.0, 0.0)].iter() {
+        kdtree.add([x], value).unwrap();
+    }
+    let distance = |a: &[f64], b: &[f64]| (a[0] - b[0]).abs();
+    let idw = |x: f64, k, power| {
+        kdtree
+            .idw_interpolate(&[x], k, power, &distance, |&v| v)
+            .unwrap()
+    };
+    // weights 1 and 1 halfway between two points
+    assert_eq!(idw(0.5, 2, 1.0), 15.0);
+    // weights 1 / 0.5^2 = 4 and twice 1 / 1.5^2 = 4 / 9
+    let expected = (4.0 * 20.0 + 4.0 / 9.0 * (10.0 + 40.0)) / (4.0 + 8.0 / 9.0);
+    assert!((idw(1.5, 3, 2.0) - expected).abs() < 1e-12);
+    // an exact match wins outright
+    assert_eq!(idw(3.0, 4, 2.0), 40.0);
+    assert!(idw(9.0, 4, 8.0) < 1.0);
+
+    assert_eq!(
+        kdtree.idw_interpolate(&[0.0], 0, 2.0, &distance, |&v| v),
+        Err(ErrorKind::NoPoints)
+    );
+    let empty: KdTree<f64, f64, [f64; 1]> = KdTree::new(1);
+    assert_eq!(
+        empty.idw_interpolate(&[0.0], 3, 2.0, &distance, |&v| v),
+        Err(ErrorKind::NoPoints)
+    );
+    assert_eq!(
+        kdtree.idw_interpolate(&[0.0, 1.0], 2, 2.0, &distance, |&v| v),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_finds_the_kth_distance() {
+    let mut kdtree = KdTree::with_capacity(2, 3);
+    for i in 0..50 {
+        kdtree
+            .add([(i % 10) as f64 * 0.7, (i / 10) as f64 * 1.3], i)
+            .unwrap();
+    }
+    for query in [[0.0, 0.0], [3.3, 2.1], [-4.0, 9.0]].iter() {
+        for k in 1..=50 {
+            let nearest = kdtree.nearest(query, k, &squared_euclidean).unwrap();
+            assert_eq!(
+                kdtree.kth_distance(query, k, &squared_euclidean).unwrap(),
+                Some(nearest.last().unwrap().0)
+            );
+        }
+        assert_eq!(kdtree.kth_distance(query, 0, &squared_euclidean), Ok(None));
+        assert_eq!(kdtree.kth_distance(query, 51, &squared_euclidean), Ok(None));
+    }
+    assert_eq!(
+        kdtree.kth_distance(&[0.0], 1, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_works_within_a_maximum_distance() {
+    let mut kdtree = KdTree::with_capacity(1, 2);
+    for i in 0..10 {
+        kdtree.add([i as f64], i).unwrap();
+    }
+    assert_eq!(
+        kdtree
+            .nearest_within(&[0.0], 5, 4.0, &squared_euclidean)
+            .unwrap(),
+        vec![(0.0, &0), (1.0, &1), (4.0, &2)]
+    );
+    assert_eq!(
+        kdtree
+            .nearest_within(&[4.2], 2, 100.0, &squared_euclidean)
+            .unwrap(),
+        kdtree.nearest(&[4.2], 2, &squared_euclidean).unwrap()
+    );
+    assert_eq!(
+        kdtree
+            .nearest_within(&[20.0], 3, 4.0, &squared_euclidean)
+            .unwrap(),
+        vec![]
+    );
+    assert_eq!(
+        kdtree.nearest_within(&[0.0, 0.0], 1, 4.0, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_works_with_a_filter() {
+    let mut kdtree = KdTree::with_capacity(1, 2);
+    for i in 0..10 {
+        kdtree.add([i as f64], i).unwrap();
+    }
+
+    assert_eq!(
+        kdtree
+            .nearest_filtered(&[0.0], 3, &squared_euclidean, &|&i| i % 2 == 1)
+            .unwrap(),
+        vec![(1.0, &1), (9.0, &3), (25.0, &5)]
+    );
+    assert_eq!(
+        kdtree
+            .nearest_filtered(&[0.0], 3, &squared_euclidean, &|&i| i == 7)
+            .unwrap(),
+        vec![(49.0, &7)]
+    );
+    assert_eq!(
+        kdtree
+            .nearest_filtered(&[0.0], 3, &squared_euclidean, &|_| false)
+            .unwrap(),
+        vec![]
+    );
+}
+
+#[test]
+fn it_works_approximately() {
+    let mut kdtree = KdTree::with_capacity(2, 4);
+    for x in 0..20 {
+        for y in 0..20 {
+            let pos = [(x as f64 * 1.37) % 17.0, (y as f64 * 2.11) % 13.0];
+            kdtree.add(pos, x * 20 + y).unwrap();
+        }
+    }
+
+    for query in [[0.0, 0.0], [7.2, 3.9], [19.0, 10.5], [-4.0, 25.0]].iter() {
+        let exact = kdtree.nearest(query, 8, &squared_euclidean).unwrap();
+        assert_eq!(
+            kdtree
+                .nearest_approx(query, 8, 0.0, &squared_euclidean)
+                .unwrap(),
+            exact
+        );
+        let approx = kdtree
+            .nearest_approx(query, 8, 0.5, &squared_euclidean)
+            .unwrap();
+        assert_eq!(approx.len(), exact.len());
+        for (a, e) in approx.iter().zip(exact.iter()) {
+            assert!(a.0 >= e.0 && a.0 <= e.0 * 1.5);
+        }
+    }
+
+    for &epsilon in [-0.5, f64::NAN].iter() {
+        assert_eq!(
+            kdtree.nearest_approx(&[0.0, 0.0], 8, epsilon, &squared_euclidean),
+            Err(ErrorKind::InvalidRadius)
+        );
+    }
+    assert_eq!(
+        kdtree.nearest_approx(&[0.0], 8, f64::NAN, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_works_with_other_metrics() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    kdtree.add([0f64, 0f64], 0).unwrap();
+    kdtree.add([2f64, 2f64], 1).unwrap();
+    kdtree.add([3.5f64, 0f64], 2).unwrap();
+    kdtree.add([0f64, -5f64], 3).unwrap();
+
+    assert_eq!(
+        kdtree.nearest(&[0.0, 0.0], 4, &manhattan).unwrap(),
+        vec![(0.0, &0), (3.5, &2), (4.0, &1), (5.0, &3)]
+    );
+    assert_eq!(
+        kdtree.nearest(&[0.0, 0.0], 4, &chebyshev).unwrap(),
+        vec![(0.0, &0), (2.0, &1), (3.5, &2), (5.0, &3)]
+    );
+    assert_eq!(
+        kdtree.within(&[2.0, 0.0], 2.0, &chebyshev).unwrap(),
+        vec![(1.5, &2), (2.0, &0), (2.0, &1)]
+    );
+}
+
+#[test]
+fn it_prunes_correctly_with_squared_distances() {
+    let mut points = vec![];
+    let mut kdtree = KdTree::with_capacity(3, 2);
+    for i in 0..500 {
+        let point = [
+            ((i * 37) % 101) as f64 * 0.13,
+            ((i * 53) % 97) as f64 * 0.29,
+            ((i * 71) % 89) as f64 * 0.07,
+        ];
+        kdtree.add(point, i).unwrap();
+        points.push((point, i));
+    }
+    let euclidean = |a: &[f64], b: &[f64]| squared_euclidean(a, b).sqrt();
+
+    for query in [[3.3, 12.1, 2.9], [-4.0, 40.0, 0.0], [6.5, 0.0, 7.0]].iter() {
+        let mut expected: Vec<_> = points
+            .iter()
+            .map(|&(ref p, i)| (squared_euclidean(query, p), i))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.truncate(10);
+
+        let squared = kdtree.nearest(query, 10, &squared_euclidean).unwrap();
+        let squared: Vec<_> = squared.into_iter().map(|(d, &i)| (d, i)).collect();
+        assert_eq!(squared, expected);
+
+        let rooted = kdtree.nearest(query, 10, &euclidean).unwrap();
+        for (&(d, i), &(d2, j)) in rooted.iter().zip(squared.iter()) {
+            assert_eq!(*i, j);
+            assert_eq!(d, d2.sqrt());
+        }
+    }
+}
+
+#[test]
+fn it_exposes_its_search_primitives() {
+    let mut heap = BinaryHeap::new();
+    for &(distance, element) in [(2.0, 'b'), (3.0, 'c'), (1.0, 'a')].iter() {
+        heap.push(HeapElement { distance, element });
+    }
+    assert_eq!(heap.peek().unwrap().element, 'c');
+    let order: Vec<(f64, char)> = heap.into_sorted_vec().into_iter().map(Into::into).collect();
+    assert_eq!(order, vec![(1.0, 'a'), (2.0, 'b'), (3.0, 'c')]);
+
+    let (min, max) = ([1.0, 1.0], [2.0, 3.0]);
+    assert_eq!(
+        distance_to_space(&[0.0, 5.0], &min, &max, &squared_euclidean),
+        5.0
+    );
+    assert_eq!(
+        distance_to_space(&[1.5, 2.0], &min, &max, &squared_euclidean),
+        0.0
+    );
+}
+
+#[test]
+fn it_builds_a_knn_graph() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    let mut points = vec![];
+    for i in 0..60 {
+        let point = [((i * 7) % 13) as f64, ((i * 5) % 11) as f64];
+        kdtree.add(point, i).unwrap();
+        points.push(point);
+    }
+    // a duplicate of point 0 is a neighbour of it, at distance zero
+    kdtree.add(points[0], 60).unwrap();
+    points.push(points[0]);
+
+    let graph = kdtree.knn_graph(3, &squared_euclidean).unwrap();
+    assert_eq!(graph.len(), 61);
+    for ((_, &i), neighbours) in kdtree.iter().zip(graph.iter()) {
+        assert_eq!(neighbours.len(), 3);
+        let mut expected: Vec<_> = (0..61)
+            .filter(|&j| j != i)
+            .map(|j| squared_euclidean(&points[i], &points[j]))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (&(d, &j), &e) in neighbours.iter().zip(expected.iter()) {
+            assert_ne!(i, j);
+            assert_eq!(d, e);
+        }
+        if i == 0 || i == 60 {
+            assert_eq!(neighbours[0], (0.0, if i == 0 { &60 } else { &0 }));
+        }
+    }
+    assert!(kdtree
+        .knn_graph(0, &squared_euclidean)
+        .unwrap()
+        .iter()
+        .all(Vec::is_empty));
+}
+
+#[test]
+fn it_finds_k_distances() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    let mut points = vec![];
+    for i in 0..30 {
+        let point = [((i * 7) % 13) as f64, ((i * 5) % 11) as f64];
+        kdtree.add(point, i).unwrap();
+        points.push(point);
+    }
+    kdtree.add([100.0, 100.0], 30).unwrap();
+    points.push([100.0, 100.0]);
+
+    let distances = kdtree.k_distances(4, &squared_euclidean).unwrap();
+    assert_eq!(distances.len(), 31);
+    for (&(&i, d), (_, &j)) in distances.iter().zip(kdtree.iter()) {
+        assert_eq!(i, j);
+        let mut expected: Vec<_> = (0..31)
+            .filter(|&other| other != i)
+            .map(|other| squared_euclidean(&points[i], &points[other]))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(d, expected[3]);
+    }
+    let outlier = distances.iter().find(|x| *x.0 == 30).unwrap().1;
+    assert!(distances.iter().all(|x| x.1 <= outlier));
+    #[cfg(feature = "rayon")]
+    assert_eq!(
+        kdtree.k_distances_par(4, &squared_euclidean).unwrap(),
+        distances
+    );
+
+    assert!(kdtree
+        .k_distances(0, &squared_euclidean)
+        .unwrap()
+        .iter()
+        .all(|x| x.1 == 0.0));
+    assert!(kdtree
+        .k_distances(31, &squared_euclidean)
+        .unwrap()
+        .iter()
+        .all(|x| x.1 == f64::INFINITY));
+
+    let mut unit: KdTree<f64, (), [f64; 2]> = KdTree::with_capacity(2, 1);
+    for &point in [[0.0, 0.0], [2.0, 0.0], [5.0, 5.0], [5.0, 6.0]].iter() {
+        unit.add(point, ()).unwrap();
+    }
+    let mut distances: Vec<_> = unit
+        .k_distances(1, &squared_euclidean)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.1)
+        .collect();
+    distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(distances, vec![1.0, 1.0, 4.0, 4.0]);
+    assert!(unit
+        .k_distances(3, &squared_euclidean)
+        .unwrap()
+        .iter()
+        .all(|x| x.1.is_finite()));
+    assert!(unit
+        .knn_graph(3, &squared_euclidean)
+        .unwrap()
+        .iter()
+        .all(|neighbours| neighbours.len() == 3));
+}
+
+#[test]
+fn it_works_with_metrics() {
+    let mut kdtree = KdTree::with_capacity(2, 3);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree
+                .add([x as f64 * 1.3, y as f64 * 0.7], x * 10 + y)
+                .unwrap();
+        }
+    }
+    let distances = |found: Vec<(f64, &usize)>| found.into_iter().map(|x| x.0).collect::<Vec<_>>();
+    for query in [[4.2, 1.1], [-1.0, 8.0], [20.0, 3.3]].iter() {
+        assert_eq!(
+            distances(
+                kdtree
+                    .nearest_by_metric(query, 5, &SquaredEuclidean)
+                    .unwrap()
+            ),
+            distances(kdtree.nearest(query, 5, &squared_euclidean).unwrap())
+        );
+        assert_eq!(
+            distances(kdtree.nearest_by_metric(query, 5, &Manhattan).unwrap()),
+            distances(kdtree.nearest(query, 5, &manhattan).unwrap())
+        );
+        assert_eq!(
+            distances(kdtree.nearest_by_metric(query, 5, &Chebyshev).unwrap()),
+            distances(kdtree.nearest(query, 5, &chebyshev).unwrap())
+        );
+        let euclidean = |a: &[f64], b: &[f64]| squared_euclidean(a, b).sqrt();
+        assert_eq!(
+            distances(kdtree.nearest_by_metric(query, 5, &Euclidean).unwrap()),
+            distances(kdtree.nearest(query, 5, &euclidean).unwrap())
+        );
+        let close = |a: Vec<f64>, b: Vec<f64>| {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-9)
+        };
+        assert!(close(
+            distances(kdtree.nearest(query, 5, &minkowski(2.0)).unwrap()),
+            distances(kdtree.nearest(query, 5, &euclidean).unwrap())
+        ));
+        assert!(close(
+            distances(kdtree.nearest(query, 5, &minkowski(1.0)).unwrap()),
+            distances(kdtree.nearest(query, 5, &manhattan).unwrap())
+        ));
+        // a higher order still prunes correctly
+        let cubic = minkowski(3.0);
+        let mut expected: Vec<f64> = kdtree.iter().map(|(p, _)| cubic(p, query)).collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.truncate(5);
+        assert!(close(
+            distances(kdtree.nearest(query, 5, &cubic).unwrap()),
+            expected
+        ));
+    }
+
+    struct Squashed;
+    impl Metric<f64> for Squashed {
+        fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+            (a[0] - b[0]).abs() + 0.1 * (a[1] - b[1]).abs()
+        }
+    }
+    assert_eq!(
+        kdtree.nearest_by_metric(&[0.0, 0.0], 1, &Squashed).unwrap(),
+        vec![(0.0, &0)]
+    );
+    assert_eq!(
+        kdtree.nearest_by_metric(&[0.0], 1, &Manhattan),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_works_with_weighted_dimensions() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    kdtree.add(POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add([3.0, 0.0], 4).unwrap();
+    kdtree.add([0.0, 3.0], 5).unwrap();
+    kdtree.add(POINT_D.0, POINT_D.1).unwrap();
+
+    let weights = [1.0, 10.0];
+    let distance = weighted_squared_euclidean(&weights);
+    assert_eq!(
+        kdtree.nearest(&[0.0, 1.0], 2, &distance).unwrap(),
+        vec![(10.0, &0), (19.0, &4)]
+    );
+    assert_eq!(
+        kdtree.within(&[0.0, 1.0], 10.0, &distance).unwrap(),
+        vec![(10.0, &0)]
+    );
+}
+
+#[test]
+fn it_works_with_f32() {
+    let mut kdtree: KdTree<f32, usize, [f32; 2]> = KdTree::with_capacity(2, 2);
+    kdtree.add([0f32, 0f32], 0).unwrap();
+    kdtree.add([1f32, 1f32], 1).unwrap();
+    kdtree.add([2f32, 2f32], 2).unwrap();
+    kdtree.add([3f32, 3f32], 3).unwrap();
+
+    assert_eq!(
+        kdtree
+            .nearest(&[0f32, 0f32], 3, &squared_euclidean)
+            .unwrap(),
+        vec![(0f32, &0), (2f32, &1), (8f32, &2)]
+    );
+    assert_eq!(
+        kdtree
+            .within(&[1f32, 1f32], 2f32, &squared_euclidean)
+            .unwrap(),
+        vec![(0f32, &1), (2f32, &2), (2f32, &0)]
+    );
+    assert_eq!(
+        kdtree.add([f32::NAN, 0f32], 4),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[test]
+fn it_reports_its_shape() {
+    let mut kdtree = KdTree::with_capacity(2, 1);
+    assert!(kdtree.is_empty());
+    assert_eq!(kdtree.depth(), 0);
+    assert_eq!(kdtree.leaf_count(), 1);
+    assert_eq!(kdtree.node_count(), 1);
+
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_D.0, POINT_D.1).unwrap();
+    assert!(!kdtree.is_empty());
+    assert_eq!(kdtree.depth(), 1);
+    assert_eq!(kdtree.leaf_count(), 2);
+    assert_eq!(kdtree.node_count(), 3);
+
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    assert_eq!(kdtree.depth(), 2);
+    assert_eq!(kdtree.leaf_count(), 3);
+    assert_eq!(kdtree.node_count(), 5);
+}
+
+#[test]
+fn it_maps_data_in_place() {
+    let mut kdtree = KdTree::with_capacity(2, 3);
+    for i in 0..200 {
+        kdtree
+            .add([((i * 17) % 31) as f64, ((i * 7) % 19) as f64], i)
+            .unwrap();
+    }
+    let dot = kdtree.to_dot();
+    let depth = kdtree.depth();
+    let before: Vec<_> = kdtree.iter().map(|(&p, &i)| (p, i)).collect();
+
+    let mapped = kdtree.map(|i| format!("#{}", i));
+    assert_eq!(mapped.to_dot(), dot);
+    assert_eq!(mapped.depth(), depth);
+    assert_eq!(mapped.size(), 200);
+    let after: Vec<_> = mapped.iter().map(|(&p, s)| (p, s.clone())).collect();
+    let expected: Vec<_> = before
+        .iter()
+        .map(|&(p, i)| (p, format!("#{}", i)))
+        .collect();
+    assert_eq!(after, expected);
+    assert_eq!(
+        mapped
+            .nearest_one(&[17.0, 7.0], &squared_euclidean)
+            .unwrap(),
+        Some((0.0, &"#1".to_string()))
+    );
+
+    // the mapping is called in iteration order
+    let mut calls = 0;
+    let order = mapped.map(|_| {
+        calls += 1;
+        calls
+    });
+    assert!(order.iter().map(|(_, &n)| n).eq(1..=200));
+}
+
+#[test]
+fn it_summarizes_itself_for_debugging() {
+    let mut kdtree = KdTree::with_capacity(1, 2);
+    for i in 0..4 {
+        kdtree.add([i as f64], i).unwrap();
+    }
+    assert_eq!(
+        format!("{:?}", kdtree),
+        "KdTree { dimensions: 1, size: 4, depth: 2, leaves: 3 }"
+    );
+
+    let dump = kdtree.debug_tree();
+    assert!(dump.starts_with("Stem {\n"));
+    assert_eq!(dump.matches("Leaf {").count(), 3);
+    assert!(dump.contains("split_value: 1.0,"));
+    let empty: KdTree<f64, usize, [f64; 1]> = KdTree::new(1);
+    assert!(empty.debug_tree().starts_with("Leaf {"));
+}
+
+#[test]
+fn it_renders_to_dot() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    assert_eq!(
+        kdtree.to_dot(),
+        "digraph kdtree {\n  n0 [shape=box, label=\"0 points\"];\n}\n"
+    );
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    assert_eq!(
+        kdtree.to_dot(),
+        "digraph kdtree {
+  n0 [label=\"dim 0 at 1\"];
+  n1 [shape=box, label=\"1 points\"];
+  n2 [shape=box, label=\"2 points\"];
+  n0 -> n1 [label=\"<\"];
+  n0 -> n2 [label=\">=\"];
+}
+"
+    );
+    let built = KdTree::build_with_capacity(2, 4, (0..100).map(|i| ([i as f64, 0.0], i)).collect())
+        .unwrap();
+    let dot = built.to_dot();
+    assert_eq!(dot.matches("shape=box").count(), built.leaf_count());
+    assert_eq!(
+        dot.matches(" -> ").count(),
+        2 * (built.node_count() - built.leaf_count())
+    );
+}
+
+#[test]
+fn it_reports_its_bounding_box() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    assert_eq!(kdtree.bounding_box(), None);
+    assert_eq!(kdtree.min_bounds(), &[f64::INFINITY; 2]);
+    assert_eq!(kdtree.max_bounds(), &[f64::NEG_INFINITY; 2]);
+
+    kdtree.add([1.0, -2.0], 0).unwrap();
+    kdtree.add([-3.0, 5.0], 1).unwrap();
+    kdtree.add([0.5, 0.5], 2).unwrap();
+    let min: &[f64] = &[-3.0, -2.0];
+    let max: &[f64] = &[1.0, 5.0];
+    assert_eq!(kdtree.bounding_box(), Some((min, max)));
+    assert_eq!(kdtree.min_bounds(), min);
+    assert_eq!(kdtree.max_bounds(), max);
+
+    kdtree.clear();
+    assert_eq!(kdtree.bounding_box(), None);
+}
+
+#[test]
+fn it_can_merge_trees() {
+    let mut kdtree = KdTree::with_capacity(2, 1);
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    let mut other = KdTree::with_capacity(2, 1);
+    other.add(&POINT_B.0, POINT_B.1).unwrap();
+    other.add(&POINT_D.0, POINT_D.1).unwrap();
+    other.add(&POINT_D.0, POINT_D.1).unwrap();
+
+    kdtree.merge(other).unwrap();
+    assert_eq!(kdtree.size(), 5);
+    assert_eq!(
+        kdtree.nearest(&POINT_A.0, 5, &squared_euclidean).unwrap(),
+        vec![(0f64, &0), (2f64, &1), (8f64, &2), (18f64, &3), (18f64, &3)]
+    );
+
+    kdtree.merge(KdTree::new(2)).unwrap();
+    assert_eq!(kdtree.size(), 5);
+    assert_eq!(kdtree.merge(KdTree::new(3)), Err(ErrorKind::WrongDimension));
+}
+
+#[test]
+fn it_can_add_many_points() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    assert_eq!(kdtree.add_many(vec![POINT_A, POINT_B, POINT_C]).unwrap(), 3);
+    assert_eq!(kdtree.add_many(vec![]).unwrap(), 0);
+    assert_eq!(kdtree.size(), 3);
+
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    let items = vec![
+        (vec![0.0, 0.0], 0),
+        (vec![1.0, 1.0], 1),
+        (vec![2.0], 2),
+        (vec![3.0, 3.0], 3),
+    ];
+    assert_eq!(kdtree.add_many(items), Err(ErrorKind::WrongDimension));
+    assert_eq!(kdtree.size(), 2);
+    assert_eq!(
+        kdtree.nearest(&[3.0, 3.0], 1, &squared_euclidean).unwrap(),
+        vec![(8.0, &1)]
+    );
+
+    let mut kdtree = KdTree::with_capacity(2, 0);
+    assert_eq!(kdtree.add_many(vec![POINT_A]), Err(ErrorKind::ZeroCapacity));
+}
+
+#[test]
+fn it_assigns_insertion_indices() {
+    let mut kdtree = KdTree::with_capacity(1, 2);
+    for i in 0..10 {
+        assert_eq!(kdtree.add_indexed([i as f64]).unwrap(), i);
+    }
+    assert_eq!(
+        kdtree.add_indexed([f64::NAN]),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        kdtree
+            .nearest_indices(&[4.25], 3, &squared_euclidean)
+            .unwrap(),
+        vec![(0.0625, 4), (0.5625, 5), (1.5625, 3)]
+    );
+    kdtree.remove(&[9.0], &9).unwrap();
+    assert_eq!(kdtree.add_indexed([9.0]).unwrap(), 10);
+    assert_eq!(
+        kdtree
+            .nearest_indices(&[10.0], 1, &squared_euclidean)
+            .unwrap(),
+        vec![(1.0, 10)]
+    );
+}
+
+#[test]
+fn it_compares_by_contents() {
+    let items: Vec<_> = (0..100)
+        .map(|i| ([(i % 10) as f64, (i % 7) as f64], i % 13))
+        .collect();
+    let mut added = KdTree::with_capacity(2, 2);
+    for &(point, data) in items.iter().rev() {
+        added.add(point, data).unwrap();
+    }
+    let built = KdTree::build_with_capacity(2, 16, items.clone()).unwrap();
+    assert_eq!(added, built);
+    assert_ne!(added.depth(), built.depth());
+
+    let mut changed = built.clone();
+    changed.remove(&items[5].0, &items[5].1).unwrap();
+    assert_ne!(changed, built);
+    changed.add(items[5].0, 99).unwrap();
+    assert_ne!(changed, built);
+
+    // repeated points must be matched as many times as they are stored
+    let mut doubled = KdTree::new(2);
+    doubled.add([0.0, 0.0], 1).unwrap();
+    doubled.add([0.0, 0.0], 1).unwrap();
+    let mut mixed = KdTree::new(2);
+    mixed.add([0.0, 0.0], 1).unwrap();
+    mixed.add([0.0, 0.0], 2).unwrap();
+    assert_ne!(doubled, mixed);
+    assert_ne!(mixed, doubled);
+    assert_eq!(mixed, mixed.clone());
+
+    let other: KdTree<f64, usize, [f64; 2]> = KdTree::new(3);
+    assert_ne!(KdTree::new(2), other);
+}
+
+#[test]
+fn it_can_be_iterated() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    assert_eq!(kdtree.iter().count(), 0);
+    for x in 0..5 {
+        for y in 0..5 {
+            kdtree.add([x as f64, y as f64], x * 5 + y).unwrap();
+        }
+    }
+    let mut seen: Vec<_> = kdtree
+        .iter()
+        .map(|(point, &data)| {
+            assert_eq!(*point, [(data / 5) as f64, (data % 5) as f64]);
+            data
+        })
+        .collect();
+    seen.sort();
+    assert_eq!(seen, (0..25).collect::<Vec<_>>());
+}
+
+#[test]
+fn it_can_be_iterated_by_leaf() {
+    let mut kdtree = KdTree::with_capacity(2, 4);
+    for i in 0..100 {
+        kdtree.add([(i % 10) as f64, (i / 10) as f64], i).unwrap();
+    }
+    let leaves: Vec<_> = kdtree.leaves().collect();
+    assert_eq!(leaves.len(), kdtree.leaf_count());
+    assert!(leaves
+        .iter()
+        .all(|&(points, bucket)| points.len() == bucket.len()));
+    assert!(leaves.iter().all(|&(points, _)| points.len() <= 4));
+    let flattened: Vec<_> = leaves
+        .iter()
+        .flat_map(|&(points, bucket)| points.iter().zip(bucket.iter()))
+        .collect();
+    assert_eq!(flattened, kdtree.iter().collect::<Vec<_>>());
+
+    let empty: KdTree<f64, usize, [f64; 2]> = KdTree::new(2);
+    let leaves: Vec<_> = empty.leaves().collect();
+    assert_eq!(leaves, vec![(&[][..], &[][..])]);
+}
+
+#[test]
+fn it_can_be_turned_into_an_iterator() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for i in 0..20 {
+        kdtree.add(vec![i as f64, (i * 3 % 7) as f64], i).unwrap();
+    }
+    assert_eq!((&kdtree).into_iter().count(), 20);
+    let size = kdtree.size();
+    let mut items: Vec<(Vec<f64>, usize)> = kdtree.into_iter().collect();
+    assert_eq!(items.len(), size);
+    items.sort_by_key(|item| item.1);
+    for (i, (point, data)) in items.into_iter().enumerate() {
+        assert_eq!(data, i);
+        assert_eq!(point, vec![i as f64, (i * 3 % 7) as f64]);
+    }
+
+    let empty: KdTree<f64, usize, Vec<f64>> = KdTree::new(2);
+    assert_eq!(empty.into_iter().count(), 0);
+}
+
+#[test]
+fn it_can_be_extended() {
+    let mut kdtree = KdTree::with_capacity(2, 1);
+    kdtree.extend(vec![]);
+    assert_eq!(kdtree.size(), 0);
+
+    kdtree.extend(vec![POINT_A, POINT_B, POINT_C, POINT_D]);
+    assert_eq!(kdtree.size(), 4);
+    assert_eq!(
+        kdtree.nearest(&POINT_A.0, 2, &squared_euclidean).unwrap(),
+        vec![(0f64, &0), (2f64, &1)]
+    );
+}
+
+#[test]
+#[should_panic(expected = "coordinate is not finite")]
+fn it_panics_when_extended_with_invalid_points() {
+    let mut kdtree = KdTree::with_capacity(2, 1);
+    kdtree.extend(vec![POINT_A, ([f64::NAN, 0f64], 1), POINT_C]);
+}
+
+#[test]
+fn it_can_be_collected() {
+    let items = vec![
+        ([0f64, 0f64, 0f64], 0),
+        ([1f64, 1f64, 1f64], 1),
+        ([2f64, 2f64, 2f64], 2),
+    ];
+
+    let kdtree = KdTree::from_points(3, items.clone()).unwrap();
+    assert_eq!(kdtree.size(), 3);
+    assert_eq!(
+        kdtree
+            .nearest(&[0.0, 0.0, 0.0], 2, &squared_euclidean)
+            .unwrap(),
+        vec![(0f64, &0), (3f64, &1)]
+    );
+    assert_eq!(
+        KdTree::from_points(2, items.clone()).unwrap_err(),
+        ErrorKind::WrongDimension
+    );
+
+    let kdtree: KdTree<f64, usize, [f64; 3]> = items.into_iter().collect();
+    assert_eq!(kdtree.size(), 3);
+    assert_eq!(
+        kdtree
+            .nearest(&[2.0, 2.0, 2.0], 1, &squared_euclidean)
+            .unwrap(),
+        vec![(0f64, &2)]
+    );
+}
+
+#[test]
+fn it_can_be_configured_with_a_builder() {
+    let mut kdtree = KdTreeBuilder::new()
+        .dimensions(2)
+        .capacity(1)
+        .build()
+        .unwrap();
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
+    assert_eq!(kdtree.size(), 2);
+    assert_eq!(kdtree.leaf_count(), 2);
+    assert_eq!(
+        kdtree.nearest(&POINT_A.0, 1, &squared_euclidean).unwrap(),
+        vec![(0f64, &0)]
+    );
+
+    let kdtree: KdTree<f64, usize, [f64; 2]> = KdTreeBuilder::new().dimensions(2).build().unwrap();
+    assert_eq!(kdtree.size(), 0);
+
+    assert_eq!(
+        KdTreeBuilder::new()
+            .dimensions(2)
+            .capacity(0)
+            .build::<f64, usize, [f64; 2]>()
+            .unwrap_err(),
+        ErrorKind::ZeroCapacity
+    );
+    assert_eq!(
+        KdTreeBuilder::new()
+            .build::<f64, usize, [f64; 2]>()
+            .unwrap_err(),
+        ErrorKind::WrongDimension
+    );
+}
+
+#[test]
+fn it_splits_leaves_at_the_split_threshold() {
+    let mut kdtree = KdTreeBuilder::new()
+        .dimensions(1)
+        .leaf_size(4)
+        .split_threshold(16)
+        .build()
+        .unwrap();
+    for i in 0..16 {
+        kdtree.add([i as f64], i).unwrap();
+    }
+    assert_eq!(kdtree.leaf_count(), 1);
+    kdtree.add([16.0], 16).unwrap();
+    assert_eq!(kdtree.leaf_count(), 2);
+
+    // rebuilt leaves are filled only up to the leaf size
+    kdtree.rebuild();
+    assert!(kdtree.leaves().all(|(points, _)| points.len() <= 4));
+    assert_eq!(
+        kdtree
+            .nearest_one(&[6.2], &squared_euclidean)
+            .unwrap()
+            .unwrap()
+            .1,
+        &6
+    );
+
+    assert_eq!(
+        KdTreeBuilder::new()
+            .dimensions(2)
+            .split_threshold(0)
+            .build::<f64, usize, [f64; 2]>()
+            .unwrap_err(),
+        ErrorKind::ZeroCapacity
+    );
+    // `capacity` sets both again
+    let mut kdtree = KdTreeBuilder::new()
+        .dimensions(1)
+        .split_threshold(16)
+        .capacity(2)
+        .build()
+        .unwrap();
+    for i in 0..3 {
+        kdtree.add([i as f64], i).unwrap();
+    }
+    assert_eq!(kdtree.leaf_count(), 2);
+}
+
+#[test]
+fn it_works_with_periodic_boundaries() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    kdtree.add([0.5, 5.0], 0).unwrap();
+    kdtree.add([9.5, 5.0], 1).unwrap();
+    kdtree.add([5.0, 5.0], 2).unwrap();
+    kdtree.add([5.0, 9.0], 3).unwrap();
+    kdtree.add([3.0, 4.0], 4).unwrap();
+
+    let period = [10.0, f64::INFINITY];
+    assert_eq!(
+        kdtree
+            .nearest_periodic(&[9.9, 5.0], 3, &period, &squared_euclidean)
+            .unwrap()
+            .into_iter()
+            .map(|(_, &i)| i)
+            .collect::<Vec<_>>(),
+        vec![1, 0, 4]
+    );
+    assert_eq!(
+        kdtree
+            .nearest_periodic(&[9.9, 5.0], 3, &[f64::INFINITY; 2], &squared_euclidean)
+            .unwrap(),
+        kdtree.nearest(&[9.9, 5.0], 3, &squared_euclidean).unwrap()
+    );
+
+    let period = [10.0, 10.0];
+    assert_eq!(
+        kdtree
+            .nearest_periodic(&[5.0, 0.5], 1, &period, &squared_euclidean)
+            .unwrap()
+            .into_iter()
+            .map(|(_, &i)| i)
+            .collect::<Vec<_>>(),
+        vec![3]
+    );
+    assert_eq!(
+        kdtree.nearest_periodic(&[5.0, 0.5], 1, &[10.0], &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+
+    let mut points = vec![];
+    for x in 0..12 {
+        for y in 0..7 {
+            points.push([(x as f64 * 3.7) % 10.0, (y as f64 * 1.3) % 10.0]);
+        }
+    }
+    let mut kdtree = KdTree::with_capacity(2, 3);
+    for (i, point) in points.iter().enumerate() {
+        kdtree.add(point, i).unwrap();
+    }
+    let wrapped = |a: &[f64], b: &[f64]| {
+        let dx = (a[0] - b[0]).abs() % 10.0;
+        let dy = (a[1] - b[1]).abs() % 10.0;
+        let dx = dx.min(10.0 - dx);
+        let dy = dy.min(10.0 - dy);
+        dx * dx + dy * dy
+    };
+    for query in [[0.1, 0.1], [9.9, 4.0], [5.0, 9.8], [12.0, -3.0]].iter() {
+        let mut expected = points.iter().map(|p| wrapped(query, p)).collect::<Vec<_>>();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let found = kdtree
+            .nearest_periodic(query, 5, &period, &squared_euclidean)
+            .unwrap();
+        for (f, e) in found.iter().zip(expected.iter()) {
+            assert!((f.0 - e).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn it_works_with_haversine() {
+    let mut points = vec![];
+    for lat in -8..=8 {
+        for lon in -17..=17 {
+            points.push([lat as f64 * 11.0, lon as f64 * 10.5]);
+        }
+    }
+    let mut kdtree = KdTree::with_capacity(2, 4);
+    for (i, point) in points.iter().enumerate() {
+        kdtree.add(point, i).unwrap();
+    }
+
+    for query in [[0.0, 0.0], [85.0, 100.0], [-60.0, 179.9], [12.3, -179.0]].iter() {
+        let mut expected = points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (haversine(query, p), i))
+            .collect::<Vec<_>>();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let found = kdtree.nearest_haversine(query, 6).unwrap();
+        assert_eq!(found.len(), 6);
+        for (f, e) in found.iter().zip(expected.iter()) {
+            assert_eq!(f.0, e.0);
+        }
+    }
+
+    let kdtree: KdTree<f64, usize, [f64; 3]> = KdTree::new(3);
+    assert_eq!(
+        kdtree.nearest_haversine(&[0.0, 0.0, 0.0], 1),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_works_with_cosine_distance() {
+    let mut points = vec![];
+    for i in 0..400 {
+        let angle = i as f64 * 0.37;
+        let length = 0.5 + (i % 7) as f64;
+        points.push([
+            angle.cos() * length,
+            angle.sin() * length,
+            ((i % 11) as f64 - 5.0) * 0.4,
+        ]);
+    }
+    points.push([0.0, 0.0, 0.0]);
+    let mut kdtree = KdTree::with_capacity(3, 4);
+    for (i, point) in points.iter().enumerate() {
+        kdtree.add(point, i).unwrap();
+    }
+
+    for query in [
+        [1.0, 0.0, 0.0],
+        [-0.3, 0.2, 0.9],
+        [0.0, -5.0, -1.0],
+        [0.0, 0.0, 0.0],
+    ]
+    .iter()
+    {
+        let mut expected = points
+            .iter()
+            .map(|p| cosine_distance(query, p))
+            .collect::<Vec<_>>();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let found = kdtree.nearest_cosine(query, 8).unwrap();
+        assert_eq!(found.len(), 8);
+        for (f, e) in found.iter().zip(expected.iter()) {
+            assert_eq!(f.0, *e);
+        }
+    }
+}
+
+#[test]
+fn it_counts_points_within_a_radius() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64], (x, y)).unwrap();
+        }
+    }
+    for &(query, radius) in [([4.5, 4.5], 2.0), ([0.0, 0.0], 9.0), ([-5.0, 3.0], 1.0)].iter() {
+        assert_eq!(
+            kdtree
+                .count_within(&query, radius, &squared_euclidean)
+                .unwrap(),
+            kdtree
+                .within(&query, radius, &squared_euclidean)
+                .unwrap()
+                .len()
+        );
+    }
+    assert_eq!(
+        kdtree
+            .count_within(&[4.5, 4.5], 0.5, &squared_euclidean)
+            .unwrap(),
+        4
+    );
+    assert_eq!(
+        kdtree.count_within(&[0.0], 1.0, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+    assert_eq!(
+        kdtree.count_within(&[f64::NAN, 0.0], 1.0, &squared_euclidean),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[test]
+fn it_finds_points_within_a_radius_unsorted() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64], x * 10 + y).unwrap();
+        }
+    }
+    for &(query, radius) in [([4.5, 4.5], 8.0), ([0.0, 0.0], 9.0), ([-5.0, 3.0], 1.0)].iter() {
+        let mut unsorted = kdtree
+            .within_unsorted(&query, radius, &squared_euclidean)
+            .unwrap();
+        unsorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut sorted = kdtree.within(&query, radius, &squared_euclidean).unwrap();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(unsorted, sorted);
+    }
+    assert_eq!(
+        kdtree.within_unsorted(&[0.0, 0.0], -1.0, &squared_euclidean),
+        Err(ErrorKind::InvalidRadius)
+    );
+}
+
+#[test]
+fn it_finds_points_within_an_annulus() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in -5..=5 {
+        for y in -5..=5 {
+            kdtree.add([x as f64, y as f64], (x, y)).unwrap();
+        }
+    }
+    let found = kdtree
+        .within_annulus(&[0.0, 0.0], 4.0, 9.0, &squared_euclidean)
+        .unwrap();
+    let expected: Vec<_> = kdtree
+        .within(&[0.0, 0.0], 9.0, &squared_euclidean)
+        .unwrap()
+        .into_iter()
+        .filter(|&(d, _)| d >= 4.0)
+        .collect();
+    assert_eq!(found.len(), expected.len());
+    assert!(found.windows(2).all(|w| w[0].0 <= w[1].0));
+    assert!(found.iter().all(|&(d, _)| (4.0..=9.0).contains(&d)));
+    for item in expected.iter() {
+        assert!(found.contains(item));
+    }
+
+    // zero-width rings keep the points exactly on them
+    assert_eq!(
+        kdtree
+            .within_annulus(&[0.0, 0.0], 25.0, 25.0, &squared_euclidean)
+            .unwrap()
+            .len(),
+        12
+    );
+    assert_eq!(
+        kdtree.within_annulus(&[0.0, 0.0], 9.0, 4.0, &squared_euclidean),
+        Err(ErrorKind::InvalidRadius)
+    );
+    assert_eq!(
+        kdtree.within_annulus(&[0.0, 0.0], f64::NAN, 4.0, &squared_euclidean),
+        Err(ErrorKind::InvalidRadius)
+    );
+    assert_eq!(
+        kdtree.within_annulus(&[0.0, 0.0], 1.0, f64::NAN, &squared_euclidean),
+        Err(ErrorKind::InvalidRadius)
+    );
+    // the point is checked before either radius
+    assert_eq!(
+        kdtree.within_annulus(&[f64::NAN, 0.0], 9.0, 4.0, &squared_euclidean),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        kdtree.within_annulus(&[0.0], -1.0, 4.0, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_folds_points_within_a_radius() {
+    let mut kdtree = KdTree::with_capacity(1, 2);
+    for i in 0..10 {
+        kdtree.add([i as f64], i).unwrap();
+    }
+    let (sum, dist) = kdtree
+        .fold_within(
+            &[4.0],
+            4.0,
+            &squared_euclidean,
+            (0, 0.0),
+            |(sum, dist), d, &i| (sum + i, dist + d),
+        )
+        .unwrap();
+    assert_eq!(sum, 2 + 3 + 4 + 5 + 6);
+    assert_eq!(dist, 10.0);
+    assert_eq!(
+        kdtree
+            .fold_within(&[40.0], 4.0, &squared_euclidean, 7, |acc, _, _| acc + 1)
+            .unwrap(),
+        7
+    );
+    assert_eq!(
+        kdtree.fold_within(&[0.0, 0.0], 1.0, &squared_euclidean, 0, |acc, _, _| acc),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_works_with_a_bounding_box() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64], x * 10 + y).unwrap();
+        }
+    }
+
+    let mut found = kdtree.within_bbox(&[2.0, 7.5], &[4.0, 9.0]).unwrap();
+    found.sort();
+    assert_eq!(found, vec![&28, &29, &38, &39, &48, &49]);
+    assert_eq!(
+        kdtree.within_bbox(&[-5.0, 3.0], &[-1.0, 4.0]).unwrap(),
+        Vec::<&usize>::new()
+    );
+    assert_eq!(
+        kdtree.within_bbox(&[3.0, 3.0], &[3.0, 3.0]).unwrap(),
+        vec![&33]
+    );
+    assert_eq!(
+        kdtree
+            .within_bbox(&[-20.0, -20.0], &[20.0, 20.0])
+            .unwrap()
+            .len(),
+        100
+    );
+
+    assert_eq!(
+        kdtree.within_bbox(&[4.0, 0.0], &[2.0, 9.0]),
+        Err(ErrorKind::InvalidBounds)
+    );
+    assert_eq!(
+        kdtree.within_bbox(&[0.0], &[2.0, 9.0]),
+        Err(ErrorKind::WrongDimension)
+    );
+    assert_eq!(
+        kdtree.within_bbox(&[0.0, 0.0], &[f64::INFINITY, 9.0]),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[test]
+fn it_finds_the_nearest_in_a_bounding_box() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64], x * 10 + y).unwrap();
+        }
+    }
+
+    let found = kdtree
+        .nearest_in_bbox(&[0.0, 0.0], 3, &[5.0, 2.5], &[7.0, 9.0], &squared_euclidean)
+        .unwrap();
+    assert_eq!(found, vec![(34.0, &53), (41.0, &54), (45.0, &63)]);
+    // every point in the box, when asking for more
+    for &query in [[6.0, 5.0], [-3.0, 20.0], [100.0, 4.4]].iter() {
+        let found = kdtree
+            .nearest_in_bbox(&query, 50, &[5.0, 2.5], &[7.0, 9.0], &squared_euclidean)
+            .unwrap();
+        assert_eq!(found.len(), 21);
+        let mut expected: Vec<_> = kdtree
+            .iter()
+            .filter(|&(p, _)| p[0] >= 5.0 && p[0] <= 7.0 && p[1] >= 2.5 && p[1] <= 9.0)
+            .map(|(p, _)| squared_euclidean(p, &query))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(found.iter().map(|x| x.0).collect::<Vec<_>>(), expected);
+    }
+    assert!(kdtree
+        .nearest_in_bbox(&[0.0, 0.0], 3, &[0.2, 0.2], &[0.8, 0.8], &squared_euclidean)
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(
+        kdtree.nearest_in_bbox(&[0.0, 0.0], 3, &[4.0, 0.0], &[2.0, 9.0], &squared_euclidean),
+        Err(ErrorKind::InvalidBounds)
+    );
+    assert_eq!(
+        kdtree.nearest_in_bbox(&[0.0], 3, &[0.0, 0.0], &[2.0, 9.0], &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_starts_from_given_bounds() {
+    let mut kdtree = KdTree::new_with_bounds(2, 2, &[0.0, 0.0], &[1.0, 1.0]).unwrap();
+    assert_eq!(kdtree.min_bounds(), &[0.0, 0.0]);
+    assert_eq!(kdtree.max_bounds(), &[1.0, 1.0]);
+    kdtree.add([0.5, 0.5], 0).unwrap();
+    assert_eq!(kdtree.max_bounds(), &[1.0, 1.0]);
+    kdtree.add([0.25, 2.0], 1).unwrap();
+    assert_eq!(kdtree.min_bounds(), &[0.0, 0.0]);
+    assert_eq!(kdtree.max_bounds(), &[1.0, 2.0]);
+    for i in 2..20 {
+        kdtree.add([i as f64 / 20.0, 0.75], i).unwrap();
+    }
+    assert_eq!(kdtree.size(), 20);
+    assert_eq!(
+        kdtree.nearest(&[0.26, 1.9], 1, &squared_euclidean).unwrap()[0].1,
+        &1
+    );
+    assert!(kdtree.validate().is_ok());
+
+    let new =
+        |min: &[f64], max: &[f64]| KdTree::<f64, usize, [f64; 2]>::new_with_bounds(2, 2, min, max);
+    assert_eq!(
+        new(&[1.0, 0.0], &[0.0, 1.0]).unwrap_err(),
+        ErrorKind::InvalidBounds
+    );
+    assert_eq!(
+        new(&[0.0, 0.0], &[1.0, f64::INFINITY]).unwrap_err(),
+        ErrorKind::NonFiniteCoordinate
+    );
+    assert_eq!(
+        new(&[0.0], &[1.0, 1.0]).unwrap_err(),
+        ErrorKind::WrongDimension
+    );
+    assert!(new(&[0.5, 0.5], &[0.5, 0.5]).is_ok());
+}
+
+#[test]
+fn it_works_with_a_slab() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64], x * 10 + y).unwrap();
+        }
+    }
+
+    let mut found = kdtree.within_slab(1, 6.5, 8.0).unwrap();
+    found.sort();
+    let expected: Vec<usize> = (0..10).flat_map(|x| vec![x * 10 + 7, x * 10 + 8]).collect();
+    assert_eq!(found, expected.iter().collect::<Vec<_>>());
+    let mut found = kdtree.within_slab(0, 9.0, f64::INFINITY).unwrap();
+    found.sort();
+    assert_eq!(
+        found,
+        (90..100).collect::<Vec<_>>().iter().collect::<Vec<_>>()
+    );
+    assert!(kdtree.within_slab(0, -5.0, -1.0).unwrap().is_empty());
+    assert_eq!(
+        kdtree
+            .within_slab(0, f64::NEG_INFINITY, f64::INFINITY)
+            .unwrap()
+            .len(),
+        100
+    );
+
+    assert_eq!(
+        kdtree.within_slab(2, 0.0, 1.0),
+        Err(ErrorKind::WrongDimension)
+    );
+    assert_eq!(
+        kdtree.within_slab(0, 2.0, 1.0),
+        Err(ErrorKind::InvalidBounds)
+    );
+    assert_eq!(
+        kdtree.within_slab(0, f64::NAN, 1.0),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[test]
+fn handles_zero_capacity() {
+    let mut kdtree = KdTree::with_capacity(2, 0);
+
+    assert_eq!(
+        kdtree.add(&POINT_A.0, POINT_A.1),
+        Err(ErrorKind::ZeroCapacity)
+    );
+    assert_eq!(
+        kdtree.nearest(&POINT_A.0, 1, &squared_euclidean).unwrap(),
+        vec![]
+    );
+    assert_eq!(
+        kdtree.nearest_one(&POINT_A.0, &squared_euclidean).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn handles_wrong_dimension() {
+    let point = ([0f64], 0f64);
+    let mut kdtree = KdTree::with_capacity(2, 1);
+
+    assert_eq!(
+        kdtree.add(&point.0, point.1),
+        Err(ErrorKind::WrongDimension)
+    );
+    assert_eq!(
+        kdtree.nearest(&point.0, 1, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+    assert_eq!(
+        kdtree.within(&point.0, 1.0, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn handles_non_finite_coordinate() {
+    let point_a = ([f64::NAN, f64::NAN], 0f64);
+    let point_b = ([f64::INFINITY, f64::INFINITY], 0f64);
+    let mut kdtree = KdTree::with_capacity(2, 1);
+
+    assert_eq!(
+        kdtree.add(&point_a.0, point_a.1),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        kdtree.add(&point_b.0, point_b.1),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        kdtree.nearest(&point_a.0, 1, &squared_euclidean),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        kdtree.nearest(&point_b.0, 1, &squared_euclidean),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        kdtree.within(&point_a.0, 1.0, &squared_euclidean),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[test]
+fn handles_invalid_radius() {
+    let mut kdtree = KdTree::with_capacity(2, 1);
+    kdtree.add([0.0, 0.0], 0).unwrap();
+    kdtree.add([1.0, 1.0], 1).unwrap();
+
+    for &radius in [f64::NAN, -1.0].iter() {
+        assert_eq!(
+            kdtree.within(&[0.0, 0.0], radius, &squared_euclidean),
+            Err(ErrorKind::InvalidRadius)
+        );
+        assert_eq!(
+            kdtree.count_within(&[0.0, 0.0], radius, &squared_euclidean),
+            Err(ErrorKind::InvalidRadius)
+        );
+        assert_eq!(
+            kdtree.nearest_within(&[0.0, 0.0], 1, radius, &squared_euclidean),
+            Err(ErrorKind::InvalidRadius)
+        );
+    }
+    // the query point is still checked first
+    assert_eq!(
+        kdtree.within(&[f64::NAN, 0.0], f64::NAN, &squared_euclidean),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        kdtree
+            .within(&[0.0, 0.0], f64::INFINITY, &squared_euclidean)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+#[cfg(not(feature = "no_std"))]
+fn handles_errors_as_std_errors() {
+    use std::error::Error;
+
+    fn add_boxed(kdtree: &mut KdTree<f64, usize, [f64; 1]>) -> Result<(), Box<dyn Error>> {
+        kdtree.add([0f64], 0)?;
+        Ok(())
+    }
+
+    let mut kdtree = KdTree::with_capacity(2, 1);
+    let err = add_boxed(&mut kdtree).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "KdTree error: point has wrong number of dimensions"
+    );
+    assert_eq!(
+        ErrorKind::NonFiniteCoordinate.to_string(),
+        "KdTree error: coordinate is not finite"
+    );
+    assert_eq!(
+        ErrorKind::ZeroCapacity.to_string(),
+        "KdTree error: capacity must be non-zero"
+    );
+}
+
+#[test]
+fn handles_singularity() {
+    let mut kdtree = KdTree::with_capacity(2, 1);
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
+    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
+    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    assert_eq!(kdtree.size(), 9);
+}
+
+#[test]
+fn handles_many_identical_points() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for i in 0..1000 {
+        kdtree.add([1.0, 2.0], i).unwrap();
+    }
+    assert_eq!(kdtree.size(), 1000);
+    assert_eq!(kdtree.leaf_count(), 1);
+    assert_eq!(
+        kdtree
+            .within(&[1.0, 2.0], 0.0, &squared_euclidean)
+            .unwrap()
+            .len(),
+        1000
+    );
+
+    // an oversized leaf still splits once a point differs, even by one ulp
+    let next = f64::from_bits(2f64.to_bits() + 1);
+    kdtree.add([1.0, next], 1000).unwrap();
+    assert_eq!(kdtree.size(), 1001);
+    assert_eq!(
+        kdtree.nearest(&[1.0, next], 1, &squared_euclidean).unwrap(),
+        vec![(0.0, &1000)]
+    );
+
+    // bounds left wide by a removal must not make identical points split
+    let mut kdtree = KdTree::new_with_strategy(1, SplitStrategy::Median);
+    kdtree.add([0.0], 0).unwrap();
+    kdtree.add([5.0], 1).unwrap();
+    kdtree.remove(&[5.0], &1).unwrap();
+    for i in 2..100 {
+        kdtree.add([0.0], i).unwrap();
+    }
+    assert_eq!(kdtree.size(), 99);
+}
+
+#[test]
+fn handles_remove() {
+    let mut kdtree = KdTree::with_capacity(2, 1);
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    kdtree.add(&POINT_D.0, POINT_D.1).unwrap();
+
+    assert_eq!(kdtree.remove(&POINT_A.0, &POINT_A.1).unwrap(), 2);
+    assert_eq!(kdtree.remove(&POINT_A.0, &POINT_A.1).unwrap(), 0);
+    assert_eq!(kdtree.remove(&POINT_B.0, &POINT_C.1).unwrap(), 0);
+    assert_eq!(kdtree.remove(&POINT_C.0, &POINT_C.1).unwrap(), 1);
+    assert_eq!(kdtree.size(), 2);
+    assert_eq!(
+        kdtree.nearest(&POINT_A.0, 4, &squared_euclidean).unwrap(),
+        vec![(2f64, &1), (18f64, &3)]
+    );
+
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    assert_eq!(kdtree.size(), 3);
+    assert_eq!(
+        kdtree.nearest(&POINT_A.0, 1, &squared_euclidean).unwrap(),
+        vec![(0f64, &0)]
+    );
+
+    assert_eq!(kdtree.remove(&[0f64], &0), Err(ErrorKind::WrongDimension));
+    assert_eq!(
+        kdtree.remove(&[f64::NAN, 0f64], &0),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[test]
+fn handles_within_on_grid() {
+    let mut points = vec![];
+    for x in 0..10 {
+        for y in 0..10 {
+            points.push(([x as f64, y as f64], x * 10 + y));
+        }
+    }
+    let mut kdtree = KdTree::with_capacity(2, 4);
+    for point in points.iter() {
+        kdtree.add(&point.0, point.1).unwrap();
+    }
+
+    let query = [4.5, 3.5];
+    let radius = 5.0;
+    let mut expected = points
+        .iter()
+        .map(|p| (squared_euclidean(&query, &p.0), p.1))
+        .filter(|&(d, _)| d <= radius)
+        .collect::<Vec<_>>();
+    expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut found = kdtree
+        .within(&query, radius, &squared_euclidean)
+        .unwrap()
+        .into_iter()
+        .map(|(d, &i)| (d, i))
+        .collect::<Vec<_>>();
+    assert!(found.windows(2).all(|w| w[0].0 <= w[1].0));
+    found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn handles_contains() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    assert!(!kdtree.contains(&POINT_A.0).unwrap());
+    kdtree.add(&POINT_A.0, POINT_A.1).unwrap();
+    kdtree.add(&POINT_B.0, POINT_B.1).unwrap();
+    kdtree.add(&POINT_C.0, POINT_C.1).unwrap();
+    kdtree.add(&POINT_D.0, POINT_D.1).unwrap();
+    kdtree.add(&POINT_D.0, POINT_D.1).unwrap();
+
+    for point in [POINT_A.0, POINT_B.0, POINT_C.0, POINT_D.0].iter() {
+        assert!(kdtree.contains(point).unwrap());
+    }
+    assert!(!kdtree.contains(&[1.0, 2.0]).unwrap());
+    assert!(!kdtree.contains(&[1.0 + 1e-12, 1.0]).unwrap());
+    kdtree.remove(&POINT_B.0, &POINT_B.1).unwrap();
+    assert!(!kdtree.contains(&POINT_B.0).unwrap());
+    assert_eq!(kdtree.contains(&[1.0]), Err(ErrorKind::WrongDimension));
+    assert_eq!(
+        kdtree.contains(&[f64::NAN, 1.0]),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[test]
+fn it_can_update_the_nearest_data() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..5 {
+        for y in 0..5 {
+            kdtree.add([x as f64, y as f64], 0).unwrap();
+        }
+    }
+    for _ in 0..3 {
+        let (dist, data) = kdtree
+            .nearest_one_mut(&[3.1, 0.9], &squared_euclidean)
+            .unwrap()
+            .unwrap();
+        assert!(dist < 0.03);
+        *data += 1;
+    }
+    assert_eq!(
+        kdtree.nearest(&[3.1, 0.9], 2, &squared_euclidean).unwrap()[0].1,
+        &3
+    );
+    let total: i32 = kdtree.iter().map(|(_, &count)| count).sum();
+    assert_eq!(total, 3);
+
+    let mut empty: KdTree<f64, i32, [f64; 2]> = KdTree::new(2);
+    assert_eq!(
+        empty.nearest_one_mut(&[0.0, 0.0], &squared_euclidean),
+        Ok(None)
+    );
+    assert_eq!(
+        empty.nearest_one_mut(&[0.0], &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_breaks_ties_by_data() {
+    let mut points = vec![];
+    for x in -3..=3 {
+        for y in -3..=3 {
+            points.push(([x as f64, y as f64], (x + 3) * 7 + (y + 3)));
+        }
+    }
+    let mut expected = points.clone();
+    expected.sort_by(|a, b| {
+        let da = squared_euclidean(&a.0, &[0.0, 0.0]);
+        let db = squared_euclidean(&b.0, &[0.0, 0.0]);
+        da.partial_cmp(&db).unwrap().then(b.1.cmp(&a.1))
+    });
+    // the tree's shape, and with it the order of ties, depends on the order
+    // of insertion
+    for &reversed in [false, true].iter() {
+        let mut kdtree = KdTree::with_capacity(2, 2);
+        let items: Vec<_> = if reversed {
+            points.iter().rev().collect()
+        } else {
+            points.iter().collect()
+        };
+        for &(point, data) in items {
+            kdtree.add(point, data).unwrap();
+        }
+        for &num in [0, 1, 3, 7, 49, 60].iter() {
+            let nearest = kdtree
+                .nearest_ordered_by(&[0.0, 0.0], num, &squared_euclidean, |a, b| b.cmp(a))
+                .unwrap();
+            let data: Vec<_> = nearest.iter().map(|&(_, &d)| d).collect();
+            let wanted: Vec<_> = expected.iter().take(num).map(|&(_, d)| d).collect();
+            assert_eq!(data, wanted);
+
+            // a metric that goes negative still orders the same way
+            let shifted = |a: &[f64], b: &[f64]| squared_euclidean(a, b) - 100.0;
+            let nearest = kdtree
+                .nearest_ordered_by(&[0.0, 0.0], num, &shifted, |a, b| b.cmp(a))
+                .unwrap();
+            assert!(nearest.iter().map(|&(_, &d)| d).eq(wanted.into_iter()));
+        }
+    }
+}
+
+#[test]
+fn it_returns_named_neighbors() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for i in 0..20 {
+        kdtree.add([i as f64, (i * 3 % 7) as f64], i).unwrap();
+    }
+    let neighbors = kdtree
+        .nearest_neighbors(&[4.2, 1.0], 5, &squared_euclidean)
+        .unwrap();
+    let nearest = kdtree.nearest(&[4.2, 1.0], 5, &squared_euclidean).unwrap();
+    assert_eq!(neighbors.len(), 5);
+    for (neighbor, &(distance, data)) in neighbors.iter().zip(nearest.iter()) {
+        assert_eq!(neighbor.distance, distance);
+        assert_eq!(neighbor.data, data);
+        assert_eq!(<(f64, &usize)>::from(*neighbor), (distance, data));
+    }
+    assert_eq!(
+        kdtree.nearest_neighbors(&[0.0], 1, &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn it_finds_its_centroid() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    assert_eq!(kdtree.centroid(), None);
+    for x in 0..10 {
+        for y in 0..5 {
+            kdtree.add([x as f64, y as f64 * 2.0], x * 5 + y).unwrap();
+        }
+    }
+    assert_eq!(kdtree.centroid(), Some(vec![4.5, 4.0]));
+}
+
+#[test]
+fn it_returns_owned_data() {
+    let owned = {
+        let mut kdtree = KdTree::with_capacity(2, 2);
+        for i in 0..20 {
+            kdtree.add([i as f64, 0.0], format!("point {}", i)).unwrap();
+        }
+        kdtree
+            .nearest_owned(&[4.2, 0.0], 2, &squared_euclidean)
+            .unwrap()
+    };
+    assert_eq!(owned.len(), 2);
+    assert_eq!(owned[0].1, "point 4");
+    assert_eq!(owned[1].1, "point 5");
+}
+
+#[test]
+fn it_finds_the_coordinates_of_data() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for i in 0..20 {
+        kdtree.add(vec![i as f64, (i * 3 % 7) as f64], i).unwrap();
+    }
+    for &(_, &i) in kdtree
+        .nearest(&[4.2, 1.0], 5, &squared_euclidean)
+        .unwrap()
+        .iter()
+    {
+        assert_eq!(
+            kdtree.coordinates_of(&i),
+            Some(&[i as f64, (i * 3 % 7) as f64][..])
+        );
+    }
+    assert_eq!(kdtree.coordinates_of(&20), None);
+
+    kdtree.remove(&[3.0, 2.0], &3).unwrap();
+    assert_eq!(kdtree.coordinates_of(&3), None);
+}
+
+#[test]
+fn it_finds_duplicate_points() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for i in 0..20 {
+        kdtree.add([(i % 10) as f64, 0.0], i).unwrap();
+    }
+    for i in 20..25 {
+        kdtree.add([3.0, 1.0], i).unwrap();
+    }
+    kdtree.add([7.5, 0.0], 25).unwrap();
+
+    let mut duplicates = kdtree.find_duplicates();
+    for &mut (_, ref mut data) in duplicates.iter_mut() {
+        data.sort();
+    }
+    duplicates.sort_by(|a, b| a.0.partial_cmp(b.0).unwrap());
+    let mut expected: Vec<(Vec<f64>, Vec<usize>)> = (0..10)
+        .map(|i| (vec![i as f64, 0.0], vec![i, i + 10]))
+        .collect();
+    expected.insert(4, (vec![3.0, 1.0], vec![20, 21, 22, 23, 24]));
+    assert_eq!(duplicates.len(), expected.len());
+    for (&(point, ref data), (p, d)) in duplicates.iter().zip(expected.iter()) {
+        assert_eq!(point, &p[..]);
+        assert_eq!(*data, d.iter().collect::<Vec<_>>());
+    }
+
+    let unique: KdTree<f64, usize, [f64; 2]> =
+        KdTree::build(2, (0..10).map(|i| ([i as f64, 0.0], i)).collect()).unwrap();
+    assert!(unique.find_duplicates().is_empty());
+}
+
+#[test]
+fn it_finds_all_data_at_a_point() {
+    let mut kdtree = KdTree::with_capacity(2, 4);
+    for i in 0..50 {
+        kdtree.add([(i % 5) as f64, 1.0], i).unwrap();
+    }
+    let mut found = kdtree.all_at(&[2.0, 1.0]).unwrap();
+    found.sort();
+    assert_eq!(
+        found,
+        (0..10)
+            .map(|i| i * 5 + 2)
+            .collect::<Vec<_>>()
+            .iter()
+            .collect::<Vec<_>>()
+    );
+    assert!(kdtree.all_at(&[2.5, 1.0]).unwrap().is_empty());
+
+    // twice the capacity at one point stays in a single, unsplit leaf
+    let mut kdtree = KdTree::with_capacity(2, 4);
+    for i in 0..8 {
+        kdtree.add([3.0, 3.0], i).unwrap();
+    }
+    assert_eq!(kdtree.leaf_count(), 1);
+    assert_eq!(kdtree.all_at(&[3.0, 3.0]).unwrap().len(), 8);
+    kdtree.add([0.0, 3.0], 8).unwrap();
+    assert_eq!(kdtree.leaf_count(), 2);
+    assert_eq!(kdtree.all_at(&[3.0, 3.0]).unwrap().len(), 8);
+    assert!(kdtree
+        .leaves()
+        .any(|(points, _)| points.len() == 8 && points.iter().all(|p| *p == [3.0, 3.0])));
+
+    assert_eq!(kdtree.all_at(&[0.0]), Err(ErrorKind::WrongDimension));
+}
+
+#[test]
+fn handles_entry() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..5 {
+        for y in 0..5 {
+            kdtree.add([x as f64, y as f64], 0).unwrap();
+        }
+    }
+    *kdtree.entry([2.0, 3.0]).unwrap().or_insert(10) += 1;
+    assert_eq!(kdtree.size(), 25);
+    assert_eq!(
+        kdtree.nearest_one(&[2.0, 3.0], &squared_euclidean).unwrap(),
+        Some((0.0, &1))
+    );
+
+    // new points, including ones that make leaves split, are added once
+    for i in 0..20 {
+        let point = [2.5 + (i % 4) as f64 * 0.01, -1.0 - (i / 4) as f64];
+        *kdtree.entry(point).unwrap().or_insert(100) += 1;
+        *kdtree
+            .entry(point)
+            .unwrap()
+            .or_insert_with(|| unreachable!()) += 1;
+    }
+    assert_eq!(kdtree.size(), 45);
+    assert_eq!(
+        kdtree
+            .nearest_one(&[2.51, -2.0], &squared_euclidean)
+            .unwrap(),
+        Some((0.0, &102))
+    );
+
+    // matching is exact
+    assert_eq!(*kdtree.entry([2.0, 3.0 + 1e-9]).unwrap().or_insert(7), 7);
+    assert_eq!(kdtree.size(), 46);
+
+    assert_eq!(
+        kdtree.entry([f64::NAN, 0.0]).err(),
+        Some(ErrorKind::NonFiniteCoordinate)
+    );
+}
+
+#[test]
+fn handles_move_point() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for i in 0..30 {
+        kdtree.add([i as f64, (i % 5) as f64], i).unwrap();
+    }
+    kdtree.add([3.0, 3.0], 3).unwrap();
+
+    // only one of the two matching points moves, across the tree
+    assert_eq!(kdtree.move_point(&[3.0, 3.0], [100.0, -50.0], &3), Ok(true));
+    assert_eq!(kdtree.size(), 31);
+    assert_eq!(
+        kdtree.nearest_one(&[3.0, 3.0], &squared_euclidean).unwrap(),
+        Some((0.0, &3))
+    );
+    assert_eq!(
+        kdtree
+            .nearest_one(&[100.0, -50.0], &squared_euclidean)
+            .unwrap(),
+        Some((0.0, &3))
+    );
+    assert_eq!(kdtree.max_bounds(), &[100.0, 4.0]);
+
+    assert_eq!(kdtree.move_point(&[3.0, 3.0], [0.0, 0.0], &4), Ok(false));
+    assert_eq!(kdtree.move_point(&[7.5, 2.0], [0.0, 0.0], &7), Ok(false));
+    assert_eq!(kdtree.size(), 31);
+
+    assert_eq!(
+        kdtree.move_point(&[4.0, 4.0], [f64::NAN, 0.0], &4),
+        Err(ErrorKind::NonFiniteCoordinate)
+    );
+    assert_eq!(
+        kdtree.move_point(&[4.0], [0.0, 0.0], &4),
+        Err(ErrorKind::WrongDimension)
+    );
+    assert_eq!(
+        kdtree.nearest_one(&[4.0, 4.0], &squared_euclidean).unwrap(),
+        Some((0.0, &4))
+    );
+}
+
+#[test]
+fn handles_remove_nearest() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for x in 0..5 {
+        for y in 0..5 {
+            kdtree.add([x as f64, y as f64], x * 5 + y).unwrap();
+        }
+    }
+    assert_eq!(
+        kdtree
+            .remove_nearest(&[1.1, 2.2], &squared_euclidean)
+            .unwrap()
+            .map(|(_, p, d)| (p, d)),
+        Some(([1.0, 2.0], 7))
+    );
+    assert_eq!(kdtree.size(), 24);
+    let (dist, _, data) = kdtree
+        .remove_nearest(&[1.1, 2.2], &squared_euclidean)
+        .unwrap()
+        .unwrap();
+    assert!((dist - 0.65).abs() < 1e-9);
+    assert_eq!(data, 8);
+
+    let mut seen = vec![];
+    while let Some((_, _, data)) = kdtree
+        .remove_nearest(&[0.0, 0.0], &squared_euclidean)
+        .unwrap()
+    {
+        seen.push(data);
+    }
+    assert_eq!(seen.len(), 23);
+    assert!(!seen.contains(&7) && !seen.contains(&8));
+    assert_eq!(kdtree.size(), 0);
+    assert_eq!(
+        kdtree.remove_nearest(&[0.0], &squared_euclidean),
+        Err(ErrorKind::WrongDimension)
+    );
+}
+
+#[test]
+fn handles_remove_within() {
+    let mut kdtree = KdTree::with_capacity(2, 3);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64], x * 10 + y).unwrap();
+        }
+    }
+    let near: Vec<usize> = kdtree
+        .within(&[4.0, 4.0], 5.0, &squared_euclidean)
+        .unwrap()
+        .into_iter()
+        .map(|(_, &i)| i)
+        .collect();
+    assert_eq!(
+        kdtree.remove_within(&[4.0, 4.0], 5.0, &squared_euclidean),
+        Ok(near.len())
+    );
+    assert_eq!(kdtree.size(), 100 - near.len());
+    assert!(kdtree
+        .within(&[4.0, 4.0], 5.0, &squared_euclidean)
+        .unwrap()
+        .is_empty());
+    assert!(kdtree.iter().all(|(_, i)| !near.contains(i)));
+    assert_eq!(
+        kdtree
+            .nearest_one(&[4.0, 4.0], &squared_euclidean)
+            .unwrap()
+            .unwrap()
+            .0,
+        8.0
+    );
+
+    assert_eq!(
+        kdtree.remove_within(&[4.0, 4.0], 5.0, &squared_euclidean),
+        Ok(0)
+    );
+    assert_eq!(
+        kdtree.remove_within(&[4.0, 4.0], -5.0, &squared_euclidean),
+        Err(ErrorKind::InvalidRadius)
+    );
+    assert_eq!(
+        kdtree.remove_within(&[0.0, 0.0], 1000.0, &squared_euclidean),
+        Ok(100 - near.len())
+    );
+    assert!(kdtree.is_empty());
+}
+
+#[test]
+fn handles_recompute_bounds() {
+    let mut kdtree = KdTree::with_capacity(2, 2);
+    for i in 0..20 {
+        kdtree.add([i as f64, (i % 4) as f64], i).unwrap();
+    }
+    kdtree.add([1000.0, -1000.0], 20).unwrap();
+    kdtree.remove(&[1000.0, -1000.0], &20).unwrap();
+    assert_eq!(
+        kdtree.bounding_box(),
+        Some((&[0.0, -1000.0][..], &[1000.0, 3.0][..]))
+    );
+
+    kdtree.recompute_bounds();
+    assert_eq!(
+        kdtree.bounding_box(),
+        Some((&[0.0, 0.0][..], &[19.0, 3.0][..]))
+    );
+    assert_eq!(kdtree.size(), 20);
+    for query in [[500.0, -500.0], [3.5, 1.0], [-10.0, 10.0]].iter() {
+        let nearest = kdtree.nearest(query, 3, &squared_euclidean).unwrap();
+        let mut expected: Vec<_> = kdtree
+            .iter()
+            .map(|(p, _)| squared_euclidean(query, p))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(nearest.iter().map(|x| x.0).eq(expected.into_iter().take(3)));
+    }
+
+    kdtree.retain(|_| false);
+    kdtree.recompute_bounds();
+    assert_eq!(kdtree.bounding_box(), None);
+}
+
+#[test]
+fn handles_retain() {
+    let mut kdtree = KdTree::with_capacity(1, 2);
+    for i in 0..20 {
+        kdtree.add([i as f64], i).unwrap();
+    }
+
+    kdtree.retain(|&i| i % 2 == 1);
+    assert_eq!(kdtree.size(), 10);
+    let mut found = kdtree
+        .within(&[10.0], 9.0, &squared_euclidean)
+        .unwrap()
+        .into_iter()
+        .map(|(_, &i)| i)
+        .collect::<Vec<_>>();
+    found.sort();
+    assert_eq!(found, vec![7, 9, 11, 13]);
+    assert_eq!(
+        kdtree
+            .nearest_one(&[6.0], &squared_euclidean)
+            .unwrap()
+            .unwrap()
+            .0,
+        1.0
+    );
+
+    kdtree.retain(|_| false);
+    assert_eq!(kdtree.size(), 0);
+    assert_eq!(
+        kdtree.nearest(&[4.0], 3, &squared_euclidean).unwrap(),
+        vec![]
+    );
+}
+
+#[test]
+fn handles_drain_filter() {
+    let mut kdtree = KdTree::with_capacity(2, 3);
+    for x in 0..10 {
+        for y in 0..10 {
+            kdtree.add([x as f64, y as f64], x * 10 + y).unwrap();
+        }
+    }
+    let in_region = |p: &[f64; 2]| p[0] >= 2.0 && p[0] <= 4.0 && p[1] < 5.0;
+
+    let drained = kdtree.drain_filter(|p, _| in_region(p));
+    assert_eq!(drained.len(), 15);
+    assert_eq!(kdtree.size(), 85);
+    assert!(drained
+        .iter()
+        .all(|&(p, i)| in_region(&p) && i == p[0] as usize * 10 + p[1] as usize));
+    assert!(kdtree.iter().all(|(p, _)| !in_region(&[p[0], p[1]])));
+
+    let mut region = KdTree::with_capacity(2, 3);
+    region.extend(drained);
+    assert_eq!(region.size(), 15);
+    assert_eq!(
+        region.nearest(&[0.0, 0.0], 1, &squared_euclidean).unwrap(),
+        vec![(4.0, &20)]
+    );
+    assert_eq!(
+        kdtree.nearest(&[3.0, 2.0], 1, &squared_euclidean).unwrap()[0].0,
+        4.0
+    );
+
+    assert!(kdtree.drain_filter(|_, _| false).is_empty());
+    assert_eq!(kdtree.drain_filter(|_, _| true).len(), 85);
+    assert!(kdtree.is_empty());
 }
 
 #[test]